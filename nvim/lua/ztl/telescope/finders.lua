@@ -43,10 +43,16 @@ function M.notes_all(opts)
 	  },
 	}
 
+	-- show first author and year of references
+	local header = entry.value.header
+	if entry.value.year ~= nil then
+	  header = string.format("%s (%s, %d)", header, (entry.value.authors or {})[1] or "", entry.value.year)
+	end
+
 	return displayer {
 	  -- text, highlight group
 	  { entry.value.kind, "notePreviewKind" },
-	  { entry.value.header, "notePreviewHeader" },
+	  { header, "notePreviewHeader" },
 	}
   end
 
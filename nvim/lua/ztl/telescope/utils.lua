@@ -20,7 +20,13 @@ function M.post_sorter(opts)
 		  return fzy_sorter:scoring_function(prompt, entry.context[1])
 		end
 
-		return fzy_sorter:scoring_function(prompt, entry.header or entry.msg)
+		-- references can also be found by author and year
+		local text = entry.header or entry.msg
+		if entry.year ~= nil then
+		  text = text .. " " .. table.concat(entry.authors or {}, " ") .. " " .. entry.year
+		end
+
+		return fzy_sorter:scoring_function(prompt, text)
 	  end
     end,
 
@@ -35,15 +41,19 @@ function M.view_to_string(tabb)
   for k in pairs(tabb) do
 	local v = tabb[k]
 	if k == "anchor" then
-	  local s = v:gsub("%.", " ")
+	  local s = v:gsub("%.", " ", 1)
 	  s = s:gsub("^%l", string.upper)
 	  return s
 	elseif k == "search" then
 	  return v
-	elseif k == "page" then
+	elseif k == "page" or k == "pagelabel" then
 	  return "p. " .. v
+	elseif k == "lines" then
+	  return "ll. " .. v.start .. "-" .. v["end"]
+	elseif k == "time" then
+	  return string.format("%d:%02d", math.floor(v.start / 60), math.floor(v.start % 60))
 	else
-	  return v
+	  return tostring(v)
 	end
   end
 end
@@ -128,11 +128,21 @@ function M.format_view(view)
   end
 
   if view.anchor ~= nil then
-	return "#anchor=" .. view.anchor
+	return "#" .. view.anchor
   elseif view.page ~= nil then
 	return "#page=" .. view.page
+  elseif view.pagelabel ~= nil then
+	return "#pagelabel=" .. view.pagelabel
   elseif view.search ~= nil then
 	return "#search=" .. view.search
+  elseif view.lines ~= nil then
+	return "#lines=" .. view.lines.start .. "-" .. view.lines["end"]
+  elseif view.time ~= nil then
+	local time = "#t=" .. view.time.start
+	if view.time["end"] ~= nil then
+	  time = time .. "," .. view.time["end"]
+	end
+	return time
   else
 	return ""
   end
@@ -51,7 +51,7 @@ dd {
         .map(|x| toml::from_str(&x).unwrap())
         .unwrap_or(IndexMap::new());
 
-    let notes = Notes::from_cache(&cfg.ztl_root())?.notes;
+    let notes = Notes::load(&cfg)?.notes;
 
     let proof_model = Model::new(
         1607392317,
@@ -0,0 +1,435 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use indexmap::IndexMap;
+
+use lsp_server::{Connection, Message, Notification, Request, Response};
+use lsp_types::{notification::{self, Notification as _}, request::{self, Request as _}, *};
+
+use ztl_base::{config::Config, notes::Notes, error::{Result, Error, ParseReport, Severity}, parser::Parsers, Key, Note};
+use crate::commands::result::Output;
+
+/// Language server for the notes of a repository
+///
+/// Positions of the cache refer to the files on disk, hence the server is
+/// only updated when a file is saved.
+struct Server {
+    config: Config,
+    parsers: Parsers,
+    notes: Notes,
+    connection: Connection,
+    /// Files with published diagnostics, which are cleared on next update
+    published: HashSet<PathBuf>,
+}
+
+fn io_error(err: impl std::fmt::Display) -> Error {
+    Error::InputOutput(std::io::Error::other(err.to_string()))
+}
+
+/// Speak the Language Server Protocol over stdio, until the client exits
+pub(crate) fn lsp(config: Config) -> Result<Output> {
+    let (connection, io_threads) = Connection::stdio();
+
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Options(TextDocumentSyncOptions {
+            open_close: Some(false),
+            save: Some(TextDocumentSyncSaveOptions::Supported(true)),
+            ..Default::default()
+        })),
+        definition_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec!["[".into(), "(".into(), "{".into(), "@".into()]),
+            ..Default::default()
+        }),
+        references_provider: Some(OneOf::Left(true)),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        ..Default::default()
+    };
+
+    connection.initialize(serde_json::to_value(capabilities).unwrap())
+        .map_err(io_error)?;
+
+    let parsers = Parsers::builtin(&config);
+    let mut server = Server {
+        notes: Notes::load(&config)?,
+        config,
+        parsers,
+        connection,
+        published: HashSet::new(),
+    };
+
+    server.update(&["**/*".to_string()])?;
+    server.run()?;
+
+    drop(server);
+    io_threads.join().map_err(io_error)?;
+
+    Ok(Output::Lsp)
+}
+
+impl Server {
+    fn run(&mut self) -> Result<()> {
+        while let Ok(msg) = self.connection.receiver.recv() {
+            match msg {
+                Message::Request(req) => {
+                    if self.connection.handle_shutdown(&req).map_err(io_error)? {
+                        return Ok(());
+                    }
+
+                    self.request(req)?;
+                },
+                Message::Notification(not) if not.method == notification::DidSaveTextDocument::METHOD => {
+                    let params: DidSaveTextDocumentParams = match serde_json::from_value(not.params) {
+                        Ok(x) => x,
+                        Err(_) => continue,
+                    };
+
+                    if let Some(path) = path(&params.text_document.uri, &self.config.root) {
+                        // dependencies (e.g. preamble) update all of their dependents
+                        let dependents = self.parsers.dependents(&self.config.root.join(&path));
+                        match dependents.is_empty() {
+                            true => self.update(&[path.display().to_string()])?,
+                            false => self.update(&dependents)?,
+                        }
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        Ok(())
+    }
+
+    fn request(&self, req: Request) -> Result<()> {
+        let id = req.id.clone();
+        let result = match req.method.as_str() {
+            request::GotoDefinition::METHOD => params::<GotoDefinitionParams>(req)
+                .map(|x| serde_json::to_value(self.definition(x.text_document_position_params))),
+            request::HoverRequest::METHOD => params::<HoverParams>(req)
+                .map(|x| serde_json::to_value(self.hover(x.text_document_position_params))),
+            request::Completion::METHOD => params::<CompletionParams>(req)
+                .map(|_| serde_json::to_value(self.completion())),
+            request::References::METHOD => params::<ReferenceParams>(req)
+                .map(|x| serde_json::to_value(self.references(x.text_document_position))),
+            request::DocumentSymbolRequest::METHOD => params::<DocumentSymbolParams>(req)
+                .map(|x| serde_json::to_value(self.symbols(&x.text_document.uri))),
+            _ => return self.send(Message::Response(Response::new_err(
+                id, lsp_server::ErrorCode::MethodNotFound as i32, "unsupported request".into()))),
+        };
+
+        let response = match result.and_then(|x| x) {
+            Ok(value) => Response::new_ok(id, value),
+            Err(err) => Response::new_err(id, lsp_server::ErrorCode::InvalidParams as i32, err.to_string()),
+        };
+
+        self.send(Message::Response(response))
+    }
+
+    fn send(&self, msg: Message) -> Result<()> {
+        self.connection.sender.send(msg).map_err(io_error)
+    }
+
+    /// Update notes from files matching the patterns, write the cache and
+    /// publish all diagnostics
+    fn update(&mut self, patterns: &[String]) -> Result<()> {
+        let mut report = ParseReport::in_root(&self.config.root);
+
+        let notes = match patterns {
+            [pattern] if pattern == "**/*" => self.notes.clone().update(&self.parsers, &self.config, &mut report),
+            _ => patterns.iter().try_fold(self.notes.clone(), |notes, pattern|
+                notes.update_files(pattern, &self.parsers, &self.config, &mut report)),
+        };
+
+        match notes {
+            Ok(mut notes) => {
+                notes.update_incoming_links();
+                notes.store(&self.config)?;
+                self.notes = notes;
+            },
+            Err(Error::Parse(x)) => report.append(x),
+            Err(err) => return Err(err),
+        }
+
+        self.publish(&report, patterns)
+    }
+
+    /// Publish diagnostics by file, previous diagnostics of files matching
+    /// the patterns are cleared
+    fn publish(&mut self, report: &ParseReport, patterns: &[String]) -> Result<()> {
+        let mut diagnostics: IndexMap<PathBuf, Vec<Diagnostic>> = IndexMap::new();
+        for diagnostic in report.iter() {
+            let source = match diagnostic.reference_source() {
+                Some(x) => x.clone(),
+                None => continue,
+            };
+
+            let content = fs::read_to_string(self.config.root.join(&source)).unwrap_or_default();
+            let span = &diagnostic.reference;
+            let start = position(&content, span.start.line, span.start.column.unwrap_or(1));
+            // inclusive end column, the whole line without column
+            let end = position(&content, span.end.line, span.end.column.map(|x| x + 1).unwrap_or(usize::MAX));
+
+            let message = match &diagnostic.help {
+                Some(help) => format!("{}\nhelp: {}", diagnostic.reason, help),
+                None => diagnostic.reason.clone(),
+            };
+
+            diagnostics.entry(source).or_default().push(Diagnostic {
+                range: Range::new(start, end),
+                severity: Some(match diagnostic.severity {
+                    Severity::Error => DiagnosticSeverity::ERROR,
+                    Severity::Warning => DiagnosticSeverity::WARNING,
+                    Severity::Note => DiagnosticSeverity::HINT,
+                }),
+                code: Some(NumberOrString::String(diagnostic.code.as_str().to_string())),
+                source: Some("ztl".to_string()),
+                message,
+                ..Default::default()
+            });
+        }
+
+        // clear diagnostics of updated files, which have no problems anymore
+        let patterns = patterns.iter().filter_map(|x| glob::Pattern::new(x).ok()).collect::<Vec<_>>();
+        let cleared = self.published.iter()
+            .filter(|x| patterns.iter().any(|p| p.matches_path(x)))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for source in cleared {
+            self.published.remove(&source);
+            diagnostics.entry(source).or_default();
+        }
+
+        for (source, diagnostics) in diagnostics {
+            if !diagnostics.is_empty() {
+                self.published.insert(source.clone());
+            }
+
+            let params = PublishDiagnosticsParams::new(uri(&self.config.root.join(&source)), diagnostics, None);
+            self.send(Message::Notification(Notification::new(notification::PublishDiagnostics::METHOD.to_string(), params)))?;
+        }
+
+        Ok(())
+    }
+
+    /// Notes with source file
+    fn notes_in(&self, path: &Path) -> impl Iterator<Item = &Note> {
+        let path = path.to_path_buf();
+        self.notes.notes.values().filter(move |x| x.span.source.as_ref() == Some(&path))
+    }
+
+    /// Note and outgoing link at position, the link is missing if the
+    /// position is outside of any link
+    fn at(&self, params: &TextDocumentPositionParams) -> Option<(&Note, Option<Key>)> {
+        let path = path(&params.text_document.uri, &self.config.root)?;
+        let content = fs::read_to_string(self.config.root.join(&path)).ok()?;
+
+        let line = params.position.line as usize + 1;
+        let column = column(&content, line, params.position.character as usize);
+
+        // innermost note, containing the line
+        let note = self.notes_in(&path)
+            .filter(|x| x.span.start.line <= line && line <= x.span.end.line + 1)
+            .max_by_key(|x| x.span.start.line)?;
+
+        let link = note.outgoing.iter()
+            .find(|x| {
+                let (start, end) = (&x.span.start, &x.span.end);
+                (start.line, start.column.unwrap_or(1)) <= (line, column)
+                    && (line, column) <= (end.line, end.column.unwrap_or(usize::MAX))
+            })
+            .map(|x| x.target.clone());
+
+        Some((note, link))
+    }
+
+    fn location(&self, note: &Note) -> Option<Location> {
+        let source = note.span.source.as_ref()?;
+        let start = Position::new(note.span.start.line.saturating_sub(1) as u32, 0);
+
+        Some(Location::new(uri(&self.config.root.join(source)), Range::new(start, start)))
+    }
+
+    fn definition(&self, params: TextDocumentPositionParams) -> Option<GotoDefinitionResponse> {
+        let target = self.at(&params)?.1?;
+        let note = self.notes.notes.get(&target)?;
+
+        self.location(note).map(GotoDefinitionResponse::Scalar)
+    }
+
+    /// Header and text of linked note, or the note at the position
+    fn hover(&self, params: TextDocumentPositionParams) -> Option<Hover> {
+        let (note, link) = self.at(&params)?;
+        let note = match link {
+            Some(target) => self.notes.notes.get(&target)?,
+            None => note,
+        };
+
+        let mut text = html_to_text(&note.html);
+        if text.chars().count() > 500 {
+            text = format!("{} …", text.chars().take(500).collect::<String>());
+        }
+
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: format!("**{}** {}\n\n{}", note.id, note.header, text),
+            }),
+            range: None,
+        })
+    }
+
+    /// All keys, filtering is left to the client
+    fn completion(&self) -> CompletionResponse {
+        let items = self.notes.notes.values().map(|note| CompletionItem {
+            label: note.id.clone(),
+            kind: Some(match note.reference {
+                Some(_) => CompletionItemKind::REFERENCE,
+                None => CompletionItemKind::FILE,
+            }),
+            detail: Some(note.header.clone()),
+            ..Default::default()
+        }).collect();
+
+        CompletionResponse::Array(items)
+    }
+
+    /// Links to the note at the position, or the linked note
+    fn references(&self, params: TextDocumentPositionParams) -> Option<Vec<Location>> {
+        let (note, link) = self.at(&params)?;
+        let target = match link {
+            Some(target) => self.notes.notes.get(&target)?,
+            None => note,
+        };
+
+        let mut locations = Vec::new();
+        for key in target.incoming.iter().collect::<HashSet<_>>() {
+            let note = match self.notes.notes.get(key) {
+                Some(x) => x,
+                None => continue,
+            };
+            let (source, content) = match note.span.source.as_ref().and_then(|x| Some((x, fs::read_to_string(self.config.root.join(x)).ok()?))) {
+                Some(x) => x,
+                None => continue,
+            };
+
+            for link in note.outgoing.iter().filter(|x| x.target == target.id) {
+                let start = position(&content, link.span.start.line, link.span.start.column.unwrap_or(1));
+                let end = position(&content, link.span.end.line, link.span.end.column.map(|x| x + 1).unwrap_or(usize::MAX));
+
+                locations.push(Location::new(uri(&self.config.root.join(source)), Range::new(start, end)));
+            }
+        }
+
+        Some(locations)
+    }
+
+    /// Notes of the document, nested by their parents
+    fn symbols(&self, uri: &Uri) -> Option<DocumentSymbolResponse> {
+        let path = path(uri, &self.config.root)?;
+        let content = fs::read_to_string(self.config.root.join(&path)).ok()?;
+        let notes = self.notes_in(&path).collect::<Vec<_>>();
+
+        #[allow(deprecated)]
+        fn symbol(note: &Note, notes: &[&Note], content: &str) -> DocumentSymbol {
+            let start = Position::new(note.span.start.line.saturating_sub(1) as u32, 0);
+            let end = position(content, note.span.end.line + 1, usize::MAX);
+
+            DocumentSymbol {
+                name: note.id.clone(),
+                detail: Some(note.header.clone()),
+                kind: SymbolKind::STRING,
+                tags: None,
+                deprecated: None,
+                range: Range::new(start, end.max(start)),
+                selection_range: Range::new(start, start),
+                children: Some(notes.iter()
+                    .filter(|x| x.parent.as_ref() == Some(&note.id))
+                    .map(|x| symbol(x, notes, content))
+                    .collect()),
+            }
+        }
+
+        // notes with parent in another file are top-level here
+        let symbols = notes.iter()
+            .filter(|x| !x.parent.as_ref().map(|p| notes.iter().any(|y| &y.id == p)).unwrap_or(false))
+            .map(|x| symbol(x, &notes, &content))
+            .collect();
+
+        Some(DocumentSymbolResponse::Nested(symbols))
+    }
+}
+
+fn params<P: serde::de::DeserializeOwned>(req: Request) -> serde_json::Result<P> {
+    serde_json::from_value(req.params)
+}
+
+/// Position of 1-based line and column, counted in UTF-16 code units as
+/// required by LSP
+fn position(content: &str, line: usize, column: usize) -> Position {
+    let text = content.lines().nth(line.saturating_sub(1)).unwrap_or("");
+    let character = text.chars().take(column.saturating_sub(1)).map(char::len_utf16).sum::<usize>();
+
+    Position::new(line.saturating_sub(1) as u32, character as u32)
+}
+
+/// 1-based column of UTF-16 offset in line
+fn column(content: &str, line: usize, character: usize) -> usize {
+    let text = content.lines().nth(line.saturating_sub(1)).unwrap_or("");
+
+    let mut units = 0;
+    text.chars().take_while(|x| { units += x.len_utf16(); units <= character }).count() + 1
+}
+
+/// File URI of absolute path, reserved characters are percent-encoded
+fn uri(path: &Path) -> Uri {
+    let path = path.display().to_string().bytes()
+        .map(|x| match x {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => (x as char).to_string(),
+            x => format!("%{:02X}", x),
+        })
+        .collect::<String>();
+
+    format!("file://{}", path).parse().unwrap()
+}
+
+/// Path relative to root of file URI
+fn path(uri: &Uri, root: &Path) -> Option<PathBuf> {
+    let path = uri.as_str().strip_prefix("file://")?;
+
+    // decode percent-encoded bytes
+    let (mut bytes, mut rest) = (Vec::new(), path.as_bytes());
+    while let Some((first, tail)) = rest.split_first() {
+        match (first, tail.get(..2).and_then(|x| u8::from_str_radix(std::str::from_utf8(x).ok()?, 16).ok())) {
+            (b'%', Some(x)) => { bytes.push(x); rest = &tail[2..] },
+            _ => { bytes.push(*first); rest = tail },
+        }
+    }
+
+    let path = PathBuf::from(String::from_utf8(bytes).ok()?);
+    ztl_base::utils::diff_paths(&path, root)
+}
+
+/// Text content of HTML with collapsed whitespace, tags are dropped
+fn html_to_text(html: &str) -> String {
+    let mut text = String::new();
+    let mut in_tag = false;
+
+    for c in html.chars() {
+        match c {
+            '<' => { in_tag = true; text.push(' ') },
+            '>' if in_tag => in_tag = false,
+            c if !in_tag => text.push(c),
+            _ => {},
+        }
+    }
+
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
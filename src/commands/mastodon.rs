@@ -5,7 +5,7 @@ use std::io::Write;
 use regex::Regex;
 use scraper::{Html, Selector};
 
-use ztl_base::{config, notes::Notes, Note, error::Result};
+use ztl_base::{config, notes::Notes, Note, View, error::Result};
 use crate::commands::{self, result::Output};
 
 pub(crate) fn publish(config: config::Config, cmds: commands::Publish) -> Result<Output> {
@@ -15,7 +15,7 @@ pub(crate) fn publish(config: config::Config, cmds: commands::Publish) -> Result
         .map(|x| toml::from_str(&x).unwrap())
         .unwrap_or(IndexMap::new());
 
-    let toot_cmd = config.toot.unwrap_or_else(|| which("toot").unwrap().display().to_string());
+    let toot_cmd = config.toot.clone().unwrap_or_else(|| which("toot").unwrap().display().to_string());
 
     if cmds.delete_all {
         for k in hash.values() {
@@ -32,7 +32,7 @@ pub(crate) fn publish(config: config::Config, cmds: commands::Publish) -> Result
     }
 
     let mut queue: Vec<String> = Vec::new();
-    let notes = Notes::from_cache(&config.root)?.notes;
+    let notes = Notes::load(&config)?.notes;
     let mut it = notes.keys();
 
     loop {
@@ -160,26 +160,21 @@ pub(crate) fn cleanup_links(content: &str, notes: &IndexMap<String, Note>, hash:
 
     for link in links {
         let href = link.attr("href").unwrap();
-        let target = notes.get(href.splitn(2, "#").next().unwrap()).unwrap();
+        let (key, view) = match href.split_once("#") {
+            Some((key, modifier)) => (key, modifier.parse::<View>().ok()),
+            None => (href, None),
+        };
+
+        let target = notes.get(key).unwrap();
         let link = match &target.resource {
             Some(x) => x,
             _ => continue,
         };
 
-        let (mut anchor, mut page) = (None, None);
-        for elm in href.split("#").skip(1) {
-            let parts = elm.splitn(2, "=").collect::<Vec<_>>();
-            if parts.len() == 1 {
-                anchor = Some(parts[0]);
-            } else if parts[0] == "page" {
-                page = Some(parts[1]);
-            }
-        }
-
-        let res = match (anchor, page) {
-            (Some(a), _) => format!("{}#{}", link, a),
-            (_, Some(a)) => format!("{}#page={}", link, a),
-            (None, None) => link.clone(),
+        // modifiers are passed on to the viewer of the resource
+        let res = match view {
+            Some(view) => format!("{}#{}", link, view),
+            None => link.clone(),
         };
 
         content = content.replace(&href, &res);
@@ -11,6 +11,8 @@ pub mod anki;
 mod schedule;
 #[cfg(feature = "mastodon")]
 mod mastodon;
+#[cfg(feature = "lsp")]
+mod lsp;
 
 pub(crate) use watch::watch;
 #[cfg(feature = "schedule")]
@@ -19,6 +21,8 @@ pub(crate) use schedule::schedule;
 pub(crate) use anki::ankify;
 #[cfg(feature = "mastodon")]
 pub(crate) use mastodon::publish;
+#[cfg(feature = "lsp")]
+pub(crate) use lsp::lsp;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = "Blaa")]
@@ -46,6 +50,38 @@ pub(crate) struct Cli {
 pub(crate) struct Build {
     #[arg(short, long)]
     pub dry_run: bool,
+    /// Fail on warnings, as if they were errors
+    #[arg(long)]
+    pub deny_warnings: bool,
+    /// Parse all files again, even if they did not change
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Parser, Debug)]
+pub(crate) struct List {
+    /// Only list references with author containing name
+    #[arg(short, long)]
+    pub author: Option<String>,
+    /// Only list references published in year or range of years (e.g. 2010-2015)
+    #[arg(short, long, value_parser = utils::parse_years)]
+    pub year: Option<(i32, i32)>,
+}
+
+#[derive(Parser, Debug)]
+pub(crate) struct Cite {
+    /// Keys of bibliography entries
+    #[arg(required = true)]
+    pub keys: Vec<String>,
+    /// Bundled CSL style (e.g. apa, ieee) or path to CSL file
+    #[arg(short, long)]
+    pub style: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+pub(crate) struct Anchors {
+    /// Only list anchors of note with key
+    pub key: Option<String>,
 }
 
 #[derive(Debug, Clone, clap::ValueEnum, PartialEq)]
@@ -81,11 +117,17 @@ pub(crate) enum Commands {
     /// Initialize a new ZTL repository
     Init,
     /// List all notes 
-    List,
-    /// Build all notes from scratch
+    List(List),
+    /// Print formatted references of bibliography entries
+    Cite(Cite),
+    /// List anchors of notes, which can be linked with `key#anchor`
+    Anchors(Anchors),
+    /// Build notes of all changed files
     Build(Build),
     /// Watch files and rebuild
     Watch(Watch),
+    /// Write cache as TOML files to `.ztl/notes` and `.ztl/files`
+    Export,
     /// Generate anki card deck (apkg) from notes
     #[cfg(feature = "anki")]
     Ankify(Ankify),
@@ -95,9 +137,13 @@ pub(crate) enum Commands {
     /// Publish notes to Mastodon instance
     #[cfg(feature = "mastodon")]
     Publish(Publish),
+    /// Language server for editors over stdio
+    #[cfg(feature = "lsp")]
+    Lsp,
 }
 
 impl Cli {
+    /// Load configuration of repository, note sources are relative to its root
     pub fn config(&self) -> Result<Config> {
         self.root.as_ref()
             .ok_or_else(|| Error::RootNotFound(self.root.clone().unwrap_or(std::path::PathBuf::new())))
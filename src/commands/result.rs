@@ -4,22 +4,32 @@ use std::fmt;
 #[cfg(feature = "schedule")]
 use colored::Colorize;
 
-use ztl_base::notes::Changes;
+use ztl_base::{notes::Changes, error::ParseReport};
 
 pub type Result = ztl_base::error::Result<Output>;
 
 #[derive(Serialize)]
 pub enum Output {
     Init { root: PathBuf, existed: bool },
-    Build { changes: Changes },
+    Build {
+        changes: Changes,
+        /// Warnings and notes, which did not fail the build
+        #[serde(skip_serializing_if = "ParseReport::is_empty")]
+        diagnostics: ParseReport,
+    },
     Analyze { nnotes: usize, nlinks: usize },
     List { notes: Vec<Note> },
+    Cite { references: Vec<(String, String)> },
+    Anchors { notes: Vec<Anchors> },
+    Export { nnotes: usize },
     #[cfg(feature = "schedule")]
     Schedule(Vec<ScheduleEntry>),
     #[cfg(feature = "mastodon")]
     Mastodon,
     #[cfg(feature = "anki")]
     Anki,
+    #[cfg(feature = "lsp")]
+    Lsp,
 }
 
 #[derive(Serialize)]
@@ -27,7 +37,19 @@ pub(crate) struct Note {
     pub(crate) key: String,
     pub(crate) header: String,
     pub(crate) kind: String,
-    pub(crate) target: String
+    pub(crate) target: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) authors: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) year: Option<i32>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct Anchors {
+    pub(crate) key: String,
+    pub(crate) anchors: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) pages: Option<usize>,
 }
 
 #[derive(Serialize)]
@@ -48,7 +70,11 @@ impl fmt::Display for Output {
                     false => write!(f, "Initialized empty ZTL repository in {}\n", root.display())?,
                 }
             },
-            Self::Build { changes } => {
+            Self::Build { changes, diagnostics } => {
+                if !diagnostics.is_empty() {
+                    write!(f, "{}\n", diagnostics)?;
+                }
+
                 if !changes.has_any() {
                     write!(f, "Updated ZTL repository, no changes\n")?;
                 } else {
@@ -59,9 +85,29 @@ impl fmt::Display for Output {
             Self::Analyze { nnotes, nlinks } => write!(f, "Found {} notes with {} outgoing links\n", nnotes, nlinks)?,
             Self::List { notes } => {
                 for note in notes {
-                    write!(f, "{} {}\n", note.key, note.header)?;
+                    match (note.authors.first(), note.year) {
+                        (Some(author), Some(year)) => write!(f, "{} {} ({}, {})\n", note.key, note.header, author, year)?,
+                        _ => write!(f, "{} {}\n", note.key, note.header)?,
+                    }
+                }
+            },
+            Self::Cite { references } => {
+                for (_, reference) in references {
+                    write!(f, "{}\n", reference)?;
+                }
+            },
+            Self::Anchors { notes } => {
+                for note in notes {
+                    for anchor in &note.anchors {
+                        write!(f, "{}#{}\n", note.key, anchor)?;
+                    }
+                    // number of pages, links to a page are `key#page=N`
+                    if let Some(pages) = note.pages {
+                        write!(f, "{} pages: {}\n", note.key, pages)?;
+                    }
                 }
             },
+            Self::Export { nnotes } => write!(f, "Exported {} notes to TOML files\n", nnotes)?,
             #[cfg(feature = "schedule")]
             Self::Schedule(entries) => {
                 for entry in entries {
@@ -72,6 +118,8 @@ impl fmt::Display for Output {
             Self::Mastodon => {},
             #[cfg(feature = "anki")]
             Self::Anki => {},
+            #[cfg(feature = "lsp")]
+            Self::Lsp => {},
         }
 
         Ok(())
@@ -179,7 +179,7 @@ let pairs: Vec<_> = kv_re
 }
 
 pub(crate) fn schedule(cfg: Config) -> Result<Output> {
-    let notes = Notes::from_cache(&cfg.ztl_root())?;
+    let notes = Notes::load(&cfg)?;
     let mut scheds = Vec::new();
 
     // capture all "<schedule <field>=<value>>" in sources
@@ -1,5 +1,6 @@
 use crossbeam_channel::unbounded;
-use notify::{Watcher, RecursiveMode};
+use notify::{Watcher, RecursiveMode, EventKind, event::ModifyKind};
+use itertools::Itertools;
 use std::process::{Command, Stdio};
 use std::io::Write;
 use std::thread;
@@ -8,8 +9,8 @@ use std::path::PathBuf;
 
 use tiny_http::{Server, Response};
 
-use ztl_base::{config, error::{Result, ParseReport}};
-use crate::{utils, commands::{result::Output, Watch}};
+use ztl_base::{config, error::{Result, ParseReport}, parser::Parsers};
+use crate::commands::{result::Output, Watch};
 
 pub(crate) fn http_server(url: String, base: PathBuf, latest: Arc<Mutex<Option<String>>>) {
     thread::spawn(move || {
@@ -36,7 +37,7 @@ pub(crate) fn http_server(url: String, base: PathBuf, latest: Arc<Mutex<Option<S
                     },
                 };
 
-                let html = note.render_html(".ztl/templates/*");
+                let html = note.render_html(base.join("templates").join("*").to_str().unwrap());
                 let response = Response::from_string(html);
                 let header = tiny_http::Header::from_bytes(&b"Content-Type"[..], &b"text/html; charset=utf8"[..]).unwrap();
                 let response = response.with_header(header);
@@ -54,25 +55,40 @@ pub(crate) fn watch(config: config::Config, cmd: &Watch) -> Result<Output> {
         http_server(config.preview.http_server.clone(), config.ztl_root(), latest.clone())
     }
 
-    let c2 = config.clone();
+    let parsers = Parsers::builtin(&config);
+
     let root = config.root.clone();
+    let watch_parsers = parsers.clone();
     let mut watcher = notify::recommended_watcher(move |res| {
         match res {
             Ok(event) => {
                 let event: notify::event::Event = event;
 
-                let path = utils::diff_paths(event.paths.first().unwrap(), &root).unwrap();
+                // changed preamble, update all dependent notes; editors may
+                // save by writing a new file or renaming a temporary one
+                let dependents = event.paths.iter()
+                    .flat_map(|x| watch_parsers.dependents(x))
+                    .unique()
+                    .collect::<Vec<_>>();
+                if !dependents.is_empty() {
+                    if let EventKind::Create(_) | EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Name(_)) = event.kind {
+                        for pattern in dependents {
+                            s.send(pattern).unwrap();
+                        }
+                    }
+
+                    return;
+                }
+
+                let path = ztl_base::utils::diff_paths(event.paths.first().unwrap(), &root).unwrap();
 
                 let path_str = path.display().to_string();
                 if path_str.contains(".ztl") && !path_str.ends_with(".sixel.show") {
                     return;
                 }
 
-                let ext = path.extension().and_then(std::ffi::OsStr::to_str);
-                if ext != Some("md") && 
-                   ext != Some("bib") && 
-                   ext != Some("tex") && 
-                   ext != Some("show") {
+                let ext = path.extension().and_then(std::ffi::OsStr::to_str).unwrap_or("");
+                if watch_parsers.for_path(&path).is_none() && ext != "show" {
                     return;
                 }
                 match event.kind {
@@ -89,14 +105,15 @@ pub(crate) fn watch(config: config::Config, cmd: &Watch) -> Result<Output> {
 
     watcher.watch(&config.root, RecursiveMode::Recursive).unwrap();
 
-    let mut report = ParseReport::empty();
+    let mut report = ParseReport::in_root(&config.root);
 
-    let mut notes = crate::Notes::from_cache(&config.ztl_root())?
-        .update_files("**/*.bib", &c2, &mut report)?
-        .update_files("**/*.md", &c2, &mut report)?
-        .update_files("**/*.tex", &c2, &mut report)?;
+    let mut notes = crate::Notes::load(&config)?
+        .update(&parsers, &config, &mut report)?;
 
-    report.as_err()?;
+    let report = report.check(false)?;
+    if !report.is_empty() {
+        println!("{}", report);
+    }
 
     println!("Watching for file changes ..");
     if cmd.http {
@@ -151,11 +168,11 @@ pub(crate) fn watch(config: config::Config, cmd: &Watch) -> Result<Output> {
             print!("{esc}c", esc = 27 as char);
 
 
-            let mut report = ParseReport::empty();
-            notes = match notes.clone().update_files(&path, &config, &mut report) {
+            let mut report = ParseReport::in_root(&config.root);
+            notes = match notes.clone().update_files(&path, &parsers, &config, &mut report) {
                 Ok(mut notes) => {
                     notes.update_incoming_links();
-                    notes.write_to_cache(&config.ztl_root())?;
+                    notes.store(&config)?;
 
                     match &mut ztl_res {
                         None => println!("{}", notes.collect_changes()),
@@ -172,8 +189,8 @@ pub(crate) fn watch(config: config::Config, cmd: &Watch) -> Result<Output> {
                 Err(err) => { println!("{}", err); notes },
             };
 
-            if let Err(err) = report.as_err() {
-                println!("{}", err);
+            if !report.is_empty() {
+                println!("{}", report);
             }
         }
     }
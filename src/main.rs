@@ -5,8 +5,8 @@ use clap::Parser;
 mod commands;
 mod utils;
 
-use ztl_base::{config::Config, notes::Notes, error::ParseReport};
-use commands::{Cli, OutputFormat, Build};
+use ztl_base::{config::Config, notes::Notes, error::{Error, ParseReport}, parser::{Parsers, Citations, BufWriteFormat}};
+use commands::{Cli, OutputFormat, Build, List, Cite, Anchors};
 use commands::result::{Result, Output};
 
 fn main() -> anyhow::Result<()> {
@@ -18,7 +18,10 @@ fn main() -> anyhow::Result<()> {
         None => analyze(cfg?),
         Some(commands::Commands::Init) => init(cli),
         Some(commands::Commands::Build(ref cmd)) => build(cfg?, cmd),
-        Some(commands::Commands::List) => list(cfg?),
+        Some(commands::Commands::List(ref cmd)) => list(cfg?, cmd),
+        Some(commands::Commands::Cite(ref cmd)) => cite(cfg?, cmd, &format),
+        Some(commands::Commands::Anchors(ref cmd)) => anchors(cfg?, cmd),
+        Some(commands::Commands::Export) => export(cfg?),
         Some(commands::Commands::Watch(ref cmd)) => commands::watch(cfg?, cmd),
         #[cfg(feature = "anki")]
         Some(commands::Commands::Ankify(ankify)) => commands::ankify(cfg?, &ankify.out),
@@ -26,6 +29,8 @@ fn main() -> anyhow::Result<()> {
         Some(commands::Commands::Schedule) => commands::schedule(cfg?),
         #[cfg(feature = "mastodon")]
         Some(commands::Commands::Publish(res)) => commands::publish(cfg?, res),
+        #[cfg(feature = "lsp")]
+        Some(commands::Commands::Lsp) => commands::lsp(cfg?),
     };
 
     match format {
@@ -63,45 +68,126 @@ fn init(cli: Cli) -> Result {
 }
 
 fn build(config: Config, cmd: &Build) -> Result {
-    let mut report = ParseReport::empty();
+    let mut report = ParseReport::in_root(&config.root);
+    let parsers = Parsers::builtin(&config);
+
+    let mut notes = Notes::load(&config)?;
+    if cmd.force {
+        notes.forget_stamps();
+    }
 
     // update notes from files in repository
-    let mut notes = Notes::from_cache(&config.ztl_root())?
-        .update_files("**/*.bib", &config, &mut report)?
-        .update_files("**/*.md", &config, &mut report)?
-        .update_files("**/*.tex", &config, &mut report)?;
+    let mut notes = notes.update(&parsers, &config, &mut report)?;
 
     notes.update_incoming_links();
+    notes.unused_entries(&mut report);
 
     if !cmd.dry_run {
-        notes.write_to_cache(&config.ztl_root())?;
+        notes.store(&config)?;
     }
 
-    report.as_err()
-        .map(|_| Output::Build { changes: notes.collect_changes()})
+    report.check(cmd.deny_warnings)
+        .map(|diagnostics| Output::Build { changes: notes.collect_changes(), diagnostics })
 }
 
 fn analyze(config: Config) -> Result {
-    let (nnotes, nlinks) = Notes::from_cache(&config.ztl_root())?.notes.values()
+    // count rows instead of reading all notes
+    #[cfg(feature = "sqlite")]
+    if config.cache.backend == ztl_base::config::CacheBackend::Sqlite {
+        let (nnotes, nlinks) = ztl_base::database::Database::open(&config.ztl_root())?.count()?;
+
+        return Ok(Output::Analyze { nnotes, nlinks });
+    }
+
+    let (nnotes, nlinks) = Notes::load(&config)?.notes.values()
         .fold((0, 0), |a,b| (a.0 + 1, a.1 + b.outgoing.len()));
 
     Ok(Output::Analyze { nnotes, nlinks })
 }
 
-fn list(config: Config) -> Result {
-    let notes = Notes::from_cache(&config.ztl_root())?;
+fn list(config: Config, cmd: &List) -> Result {
+    let notes = Notes::load(&config)?;
+
+    // filter by bibliographic metadata, if requested
+    let matches = |note: &ztl_base::Note| {
+        if cmd.author.is_none() && cmd.year.is_none() {
+            return true;
+        }
+
+        let reference = match &note.reference {
+            Some(x) => x,
+            None => return false,
+        };
+
+        cmd.author.as_ref().map(|x| reference.has_author(x)).unwrap_or(true)
+            && cmd.year.map(|(start, end)| reference.year.map(|x| x >= start && x <= end).unwrap_or(false)).unwrap_or(true)
+    };
 
     let notes = notes.notes.values()
+        .filter(|note| matches(note))
         .map(|note| crate::commands::result::Note {
             key: note.id.clone(),
             header: note.header.replace("\\", "\\\\"), 
             kind: note.kind.as_ref().map(|x| x.as_str()).unwrap_or("note").to_string(),
-            target: format!("{}:{}", note.span.source.as_ref().map(|x| x.display().to_string()).unwrap_or(String::new()), note.span.start.line)
+            target: match note.span.has_lines() {
+                true => format!("{}:{}", note.span.source.as_ref().map(|x| x.display().to_string()).unwrap_or(String::new()), note.span.start.line),
+                false => note.span.source.as_ref().map(|x| x.display().to_string()).unwrap_or(String::new()),
+            },
+            authors: note.reference.as_ref().map(|x| x.authors.clone()).unwrap_or_default(),
+            year: note.reference.as_ref().and_then(|x| x.year),
         })
         .collect::<Vec<_>>();
 
     Ok(Output::List { notes })
 }
 
+fn cite(config: Config, cmd: &Cite, format: &OutputFormat) -> Result {
+    let notes = Notes::load_keys(&config, &cmd.keys)?;
 
+    let style = cmd.style.as_ref().unwrap_or(&config.bibliography.style);
+    let citations = Citations::new(&config, style)?;
+
+    // print plain text to terminal, and HTML for other tools
+    let format = match format {
+        OutputFormat::Human => BufWriteFormat::Plain,
+        OutputFormat::JSON => BufWriteFormat::Html,
+    };
+
+    let library = ztl_base::parser::load_library(&notes, &config.root, &cmd.keys)?;
+    let references = citations.format(&library, format, true)
+        .into_iter()
+        .collect();
+
+    Ok(Output::Cite { references })
+}
 
+fn anchors(config: Config, cmd: &Anchors) -> Result {
+    // a single note is read by its key
+    let notes = match &cmd.key {
+        Some(key) => Notes::load_keys(&config, &[key.clone()])?,
+        None => Notes::load(&config)?,
+    };
+
+    if let Some(key) = cmd.key.as_ref().filter(|x| !notes.notes.contains_key(*x)) {
+        return Err(Error::UnknownNote(key.clone()));
+    }
+
+    // notes without anchors and pages are skipped, unless requested
+    let notes = notes.notes.values()
+        .filter(|note| cmd.key.as_ref().map(|x| *x == note.id).unwrap_or(note.anchors.as_ref().map(|x| !x.is_empty()).unwrap_or(false) || note.pages.is_some()))
+        .map(|note| crate::commands::result::Anchors {
+            key: note.id.clone(),
+            anchors: note.anchors.clone().unwrap_or_default(),
+            pages: note.pages,
+        })
+        .collect::<Vec<_>>();
+
+    Ok(Output::Anchors { notes })
+}
+
+fn export(config: Config) -> Result {
+    let notes = Notes::load(&config)?;
+    notes.write_to_cache(&config.ztl_root())?;
+
+    Ok(Output::Export { nnotes: notes.notes.len() })
+}
@@ -1,4 +1,4 @@
-use std::path::{Path, PathBuf, Component};
+use std::path::{Path, PathBuf};
 
 pub(crate) fn search_upwards<F: Fn(&Path) -> bool>(p: &Path, cond: F) -> Option<&Path> {
     let mut cwd = Some(p);
@@ -26,48 +26,12 @@ pub(crate) fn find_root(arg: &str) -> std::result::Result<PathBuf, String> {
         .unwrap_or(PathBuf::new()))
 }
 
-pub fn diff_paths<P, B>(path: P, base: B) -> Option<PathBuf>
-where
-    P: AsRef<Path>,
-    B: AsRef<Path>,
-{
-    let path = path.as_ref();
-    let base = base.as_ref();
+/// Parse single year `2010` or inclusive range `2010-2015`
+pub(crate) fn parse_years(arg: &str) -> std::result::Result<(i32, i32), String> {
+    let parse = |x: &str| x.trim().parse::<i32>().map_err(|_| format!("invalid year `{}`", x));
 
-    if path.is_absolute() != base.is_absolute() {
-        if path.is_absolute() {
-            Some(PathBuf::from(path))
-        } else {
-            None
-        }
-    } else {
-        let mut ita = path.components();
-        let mut itb = base.components();
-        let mut comps: Vec<Component> = vec![];
-        loop {
-            match (ita.next(), itb.next()) {
-                (None, None) => break,
-                (Some(a), None) => {
-                    comps.push(a);
-                    comps.extend(ita.by_ref());
-                    break;
-                }
-                (None, _) => comps.push(Component::ParentDir),
-                (Some(a), Some(b)) if comps.is_empty() && a == b => (),
-                (Some(a), Some(b)) if b == Component::CurDir => comps.push(a),
-                (Some(_), Some(b)) if b == Component::ParentDir => return None,
-                (Some(a), Some(_)) => {
-                    comps.push(Component::ParentDir);
-                    for _ in itb {
-                        comps.push(Component::ParentDir);
-                    }
-                    comps.push(a);
-                    comps.extend(ita.by_ref());
-                    break;
-                }
-            }
-        }
-        Some(comps.iter().map(|c| c.as_os_str()).collect())
+    match arg.split_once("-") {
+        Some((start, end)) => Ok((parse(start)?, parse(end)?)),
+        None => parse(arg).map(|x| (x, x)),
     }
 }
-
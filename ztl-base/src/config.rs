@@ -5,12 +5,108 @@ use serde::Deserialize;
 
 use crate::error::Result;
 
+/// LaTeX notes, converted to HTML with one of the supported backends
 #[derive(Deserialize, Debug, Clone)]
 pub struct Latex {
+    /// Preamble of all notes, relative to the ZTL folder
     pub preamble: PathBuf,
+    #[serde(default)]
+    pub backend: LatexBackend,
+    /// Command template, overrides the default command of the backend
+    pub build: Option<String>,
+    /// Configuration file passed to the backend (e.g. `.cfg` for make4ht),
+    /// relative to the ZTL folder
+    pub cfg: Option<PathBuf>,
+}
+
+/// Built-in profiles for LaTeX to HTML conversion
+///
+/// Each backend has its own default command, error format and HTML post-processing.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LatexBackend {
+    #[default]
+    Make4ht,
+    Pandoc,
+    Latexml,
+}
+
+impl LatexBackend {
+    /// Default command template of the backend
+    ///
+    /// Placeholders are `{file}`, `{output}`, `{dir}`, `{root}` and `{cfg}`,
+    /// they are quoted when substituted.
+    pub fn command(&self, cfg: bool) -> &'static str {
+        match (self, cfg) {
+            (LatexBackend::Make4ht, false) => "make4ht -a debug -m draft {file}",
+            (LatexBackend::Make4ht, true) => "make4ht -a debug -c {cfg} -m draft {file}",
+            (LatexBackend::Pandoc, _) => "pandoc --mathml -f latex -t html -o {output} {file}",
+            (LatexBackend::Latexml, _) => "latexml --quiet --dest=main.xml {file} && latexmlpost --quiet --format=html5 --dest={output} main.xml",
+        }
+    }
+}
+
+/// Typst notes, compiled to HTML with an external command
+#[derive(Deserialize, Debug, Clone)]
+pub struct Typst {
+    /// Preamble of all notes, relative to the ZTL folder
+    #[serde(default)]
+    pub preamble: Option<PathBuf>,
+    #[serde(default = "default_typst_build")]
     pub build: String,
 }
 
+impl Default for Typst {
+    fn default() -> Self {
+        Typst { preamble: None, build: default_typst_build() }
+    }
+}
+
+/// Formatting of bibliography notes with a CSL style
+#[derive(Deserialize, Debug, Clone)]
+pub struct Bibliography {
+    /// Name of a bundled style (e.g. `apa`, `ieee`) or path to a CSL file
+    #[serde(default = "default_csl_style")]
+    pub style: String,
+    /// Locale of terms, defaults to the locale of the style
+    pub locale: Option<String>,
+}
+
+impl Default for Bibliography {
+    fn default() -> Self {
+        Bibliography { style: default_csl_style(), locale: None }
+    }
+}
+
+/// Local Zotero library, read without modifying it
+#[derive(Deserialize, Debug, Clone)]
+pub struct Zotero {
+    /// Path to `zotero.sqlite`, relative to root or absolute
+    pub database: PathBuf,
+    /// Directory of attachments, defaults to `storage` next to the database
+    pub storage: Option<PathBuf>,
+}
+
+/// Storage of the note cache in the ZTL folder
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheBackend {
+    /// One TOML file per note in `notes` and per source in `files`
+    #[default]
+    Toml,
+    /// Single database `cache.sqlite`, requires the `sqlite` feature
+    Sqlite,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Cache {
+    #[serde(default)]
+    pub backend: CacheBackend,
+    /// Write the TOML files as well, for plugins reading them
+    #[serde(default)]
+    pub export: bool,
+}
+
 /// Preview notes with defined template and geckodriver
 #[derive(Deserialize, Debug, Clone)]
 pub struct Preview {
@@ -23,6 +119,13 @@ pub struct Preview {
 #[derive(Deserialize, Debug, Clone)]
 pub struct Config {
     pub latex: Latex,
+    #[serde(default)]
+    pub typst: Typst,
+    #[serde(default)]
+    pub bibliography: Bibliography,
+    pub zotero: Option<Zotero>,
+    #[serde(default)]
+    pub cache: Cache,
     pub preview: Preview,
     pub toot: Option<String>,
     #[serde(default)]
@@ -36,7 +139,11 @@ impl Config {
         let config = fs::read_to_string(&root.join(".ztl").join("config"))?;
         let mut config: Config = toml::from_str(&config)?;
 
-        config.public = config.public.into_iter().map(|x| glob::glob(&x).unwrap()).flatten().map(|x| x.unwrap().display().to_string()).collect();
+        let prefix = glob::Pattern::escape(root.to_str().unwrap());
+        config.public = config.public.into_iter()
+            .map(|x| glob::glob(&format!("{}/{}", prefix, x)).unwrap()).flatten()
+            .filter_map(|x| x.ok()?.strip_prefix(root).ok().map(|x| x.display().to_string()))
+            .collect();
         config.root = root.to_path_buf();
 
         Ok(config)
@@ -47,7 +154,52 @@ impl Config {
     }
 
     pub fn latex_preamble(&self) -> PathBuf {
-        self.root.join(".ztl").join(&self.latex.preamble)
+        self.ztl_root().join(&self.latex.preamble)
+    }
+
+    /// Configuration file of the backend, make4ht falls back to `thmtav.cfg`
+    /// in the ZTL folder if it exists
+    pub fn latex_cfg(&self) -> Option<PathBuf> {
+        match &self.latex.cfg {
+            Some(cfg) => Some(self.ztl_root().join(cfg)),
+            None => Some(self.ztl_root().join("thmtav.cfg"))
+                .filter(|x| self.latex.backend == LatexBackend::Make4ht && x.exists()),
+        }
+    }
+
+    /// Command template to convert LaTeX notes, either configured or from backend
+    pub fn latex_build(&self) -> String {
+        match &self.latex.build {
+            Some(build) => build.clone(),
+            None => self.latex.backend.command(self.latex_cfg().is_some()).to_string(),
+        }
+    }
+
+    /// Path of CSL file, if the style is not bundled
+    pub fn csl_file(&self, style: &str) -> Option<PathBuf> {
+        style.ends_with(".csl").then(|| self.root.join(style))
+    }
+
+    /// Path of Zotero database relative to root, as it is recorded in the
+    /// file cache
+    pub fn zotero_database(&self) -> Option<PathBuf> {
+        let zotero = self.zotero.as_ref()?;
+
+        crate::utils::diff_paths(self.root.join(&zotero.database), &self.root)
+    }
+
+    /// Directory of Zotero attachments
+    pub fn zotero_storage(&self) -> Option<PathBuf> {
+        let zotero = self.zotero.as_ref()?;
+
+        Some(match &zotero.storage {
+            Some(storage) => self.root.join(storage),
+            None => self.root.join(&zotero.database).with_file_name("storage"),
+        })
+    }
+
+    pub fn typst_preamble(&self) -> Option<PathBuf> {
+        self.typst.preamble.as_ref().map(|x| self.ztl_root().join(x))
     }
 
     pub fn empty(path: &Path) -> Result<()> {
@@ -58,8 +210,29 @@ public = []
 template = ".ztl/template.html"
 
 [latex]
-preamble = ".ztl/preamble.text"
-build = "/usr/bin/make4ht -m draft {file}"
+# paths are relative to the .ztl folder
+preamble = "preamble.tex"
+# one of "make4ht", "pandoc" or "latexml"
+backend = "make4ht"
+# defaults to "thmtav.cfg" for make4ht, if it exists
+# cfg = "config.cfg"
+# build = "make4ht -m draft {file}"
+
+[typst]
+build = "typst compile --features html --format html {file} {output}"
+
+[bibliography]
+# bundled style (e.g. "apa", "ieee") or path to CSL file
+style = "apa"
+
+# [zotero]
+# database = "/home/user/Zotero/zotero.sqlite"
+
+[cache]
+# one of "toml" or "sqlite" (requires the `sqlite` feature)
+backend = "toml"
+# write TOML files as well with the sqlite backend
+# export = true
 "#;
 
         let mut f = fs::File::create(path)?;
@@ -72,3 +245,11 @@ build = "/usr/bin/make4ht -m draft {file}"
 fn default_http_server() -> String {
     "127.0.0.1:1111".into()
 }
+
+fn default_typst_build() -> String {
+    "typst compile --features html --format html {file} {output}".into()
+}
+
+fn default_csl_style() -> String {
+    "apa".into()
+}
@@ -0,0 +1,244 @@
+//! Cache of notes in a single SQLite database
+//!
+//! Notes, their links and the spans of source files are kept in indexed
+//! tables, so that commands can query single notes without reading the whole
+//! cache and updates only write changed rows. Notes and file spans are stored
+//! as TOML, the same as in the files of the TOML cache.
+
+use std::path::{Path, PathBuf};
+use indexmap::IndexMap;
+use rusqlite::{Connection, OptionalExtension, params};
+
+use crate::{Note, File, FileSpan, Stamp, notes::Notes, error::{Error, Result}};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS notes (
+    id TEXT PRIMARY KEY,
+    header TEXT NOT NULL,
+    kind TEXT,
+    parent TEXT,
+    source TEXT,
+    line INTEGER NOT NULL,
+    data TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS links (
+    note TEXT NOT NULL,
+    target TEXT NOT NULL,
+    label TEXT NOT NULL,
+    view TEXT,
+    line INTEGER NOT NULL,
+    column INTEGER
+);
+CREATE INDEX IF NOT EXISTS links_note ON links (note);
+
+CREATE TABLE IF NOT EXISTS files (
+    hash TEXT PRIMARY KEY,
+    source TEXT NOT NULL,
+    stamp TEXT
+);
+
+CREATE TABLE IF NOT EXISTS spans (
+    file TEXT NOT NULL,
+    range TEXT NOT NULL,
+    target TEXT NOT NULL,
+    data TEXT NOT NULL,
+    PRIMARY KEY (file, range)
+);
+";
+
+/// SQLite database at `.ztl/cache.sqlite`
+pub struct Database {
+    path: PathBuf,
+    conn: Connection,
+}
+
+impl Database {
+    /// Open database in ZTL folder, tables are created if missing
+    pub fn open(root: &Path) -> Result<Self> {
+        let path = root.join("cache.sqlite");
+        let conn = Connection::open(&path)
+            .and_then(|conn| conn.execute_batch(SCHEMA).map(|_| conn))
+            .map_err(|err| Error::Database(path.clone(), err.to_string()))?;
+
+        Ok(Database { path, conn })
+    }
+
+    fn err(&self) -> impl Fn(rusqlite::Error) -> Error + '_ {
+        |err| Error::Database(self.path.clone(), err.to_string())
+    }
+
+    /// Read all notes and file spans
+    pub fn load(&self) -> Result<Notes> {
+        let notes = self.query("SELECT data FROM notes ORDER BY id", [])?
+            .into_iter()
+            .map(|data| {
+                let note: Note = toml::from_str(&data)
+                    .map_err(|err| Error::InvalidNote(self.path.clone(), err))?;
+
+                Ok((note.id.clone(), note))
+            })
+            .collect::<Result<IndexMap<_, _>>>()?;
+
+        let mut spans = span_rows(&self.conn).map_err(self.err())?;
+        let mut files = IndexMap::new();
+        for (hash, source, stamp) in file_rows(&self.conn).map_err(self.err())? {
+            let stamp = stamp
+                .map(|x| toml::from_str::<Stamp>(&x).map_err(|err| Error::InvalidFileSpan(PathBuf::from(&source), err)))
+                .transpose()?;
+
+            let spans = spans.shift_remove(&hash).unwrap_or_default()
+                .into_iter()
+                .map(|(range, data)| toml::from_str::<FileSpan>(&data)
+                    .map(|span| (range, span))
+                    .map_err(|err| Error::InvalidFileSpan(PathBuf::from(&source), err)))
+                .collect::<Result<IndexMap<_, _>>>()?;
+
+            files.insert(hash, File { source: PathBuf::from(source), stamp, spans });
+        }
+
+        Ok(Notes { notes, files, changes: Vec::new() })
+    }
+
+    /// Update content of database to notes, in a single transaction
+    ///
+    /// Only rows of notes and files which differ from the stored ones are
+    /// written, rows of removed notes and files are deleted.
+    pub fn write(&mut self, notes: &Notes) -> Result<()> {
+        let path = self.path.clone();
+        let err = |err: rusqlite::Error| Error::Database(path.clone(), err.to_string());
+
+        let tx = self.conn.transaction().map_err(err)?;
+
+        {
+            let mut stored = tx.prepare("SELECT id, data FROM notes")
+                .and_then(|mut stmt| stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                    .collect::<rusqlite::Result<IndexMap<String, String>>>())
+                .map_err(err)?;
+
+            let mut delete_note = tx.prepare("DELETE FROM notes WHERE id = ?1").map_err(err)?;
+            let mut delete_links = tx.prepare("DELETE FROM links WHERE note = ?1").map_err(err)?;
+            let mut insert_note = tx.prepare("INSERT INTO notes (id, header, kind, parent, source, line, data) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)")
+                .map_err(err)?;
+            let mut insert_link = tx.prepare("INSERT INTO links (note, target, label, view, line, column) VALUES (?1, ?2, ?3, ?4, ?5, ?6)")
+                .map_err(err)?;
+
+            for note in notes.notes.values() {
+                let data = toml::to_string(note)?;
+                if stored.shift_remove(&note.id).as_ref() == Some(&data) {
+                    continue;
+                }
+
+                delete_note.execute([&note.id]).map_err(err)?;
+                delete_links.execute([&note.id]).map_err(err)?;
+                insert_note.execute(params![
+                    note.id, note.header, note.kind, note.parent,
+                    note.span.source.as_ref().map(|x| x.display().to_string()),
+                    note.span.start.line,
+                    data,
+                ]).map_err(err)?;
+
+                for link in &note.outgoing {
+                    insert_link.execute(params![
+                        note.id, link.target, link.label,
+                        link.view.as_ref().map(|x| x.to_string()),
+                        link.span.start.line, link.span.start.column,
+                    ]).map_err(err)?;
+                }
+            }
+
+            // remaining notes were removed
+            for id in stored.keys() {
+                delete_note.execute([id]).map_err(err)?;
+                delete_links.execute([id]).map_err(err)?;
+            }
+
+            let mut stored_spans = span_rows(&tx).map_err(err)?;
+            let mut stored = file_rows(&tx).map_err(err)?
+                .into_iter()
+                .map(|(hash, source, stamp)| {
+                    let spans = stored_spans.shift_remove(&hash).unwrap_or_default();
+                    (hash, (source, stamp, spans))
+                })
+                .collect::<IndexMap<_, _>>();
+
+            let mut delete_file = tx.prepare("DELETE FROM files WHERE hash = ?1").map_err(err)?;
+            let mut delete_spans = tx.prepare("DELETE FROM spans WHERE file = ?1").map_err(err)?;
+            let mut insert_file = tx.prepare("INSERT INTO files (hash, source, stamp) VALUES (?1, ?2, ?3)")
+                .map_err(err)?;
+            let mut insert_span = tx.prepare("INSERT INTO spans (file, range, target, data) VALUES (?1, ?2, ?3, ?4)")
+                .map_err(err)?;
+
+            // files without spans are skipped, as in the TOML cache
+            for (hash, file) in notes.files.iter().filter(|x| !x.1.spans.is_empty()) {
+                let source = file.source.display().to_string();
+                let stamp = file.stamp.as_ref().map(toml::to_string).transpose()?;
+                let spans = file.spans.iter()
+                    .map(|(range, span)| Ok((range.clone(), toml::to_string(span)?)))
+                    .collect::<Result<Vec<_>>>()?;
+
+                if stored.shift_remove(hash).as_ref() == Some(&(source.clone(), stamp.clone(), spans.clone())) {
+                    continue;
+                }
+
+                delete_file.execute([hash]).map_err(err)?;
+                delete_spans.execute([hash]).map_err(err)?;
+                insert_file.execute(params![hash, source, stamp]).map_err(err)?;
+
+                for ((range, data), span) in spans.iter().zip(file.spans.values()) {
+                    insert_span.execute(params![hash, range, span.target, data]).map_err(err)?;
+                }
+            }
+
+            // remaining files were removed, or have no spans anymore
+            for hash in stored.keys() {
+                delete_file.execute([hash]).map_err(err)?;
+                delete_spans.execute([hash]).map_err(err)?;
+            }
+        }
+
+        tx.commit().map_err(err)
+    }
+
+    /// Read a single note, without loading all others
+    pub fn note(&self, key: &str) -> Result<Option<Note>> {
+        let data = self.conn.query_row("SELECT data FROM notes WHERE id = ?1", [key], |row| row.get::<_, String>(0))
+            .optional()
+            .map_err(self.err())?;
+
+        data.map(|x| toml::from_str(&x).map_err(|err| Error::InvalidNote(self.path.clone(), err)))
+            .transpose()
+    }
+
+    /// Number of notes and links
+    pub fn count(&self) -> Result<(usize, usize)> {
+        self.conn.query_row("SELECT (SELECT COUNT(*) FROM notes), (SELECT COUNT(*) FROM links)", [], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(self.err())
+    }
+
+    /// Single text column of all rows
+    fn query<P: rusqlite::Params>(&self, sql: &str, params: P) -> Result<Vec<String>> {
+        self.conn.prepare(sql)
+            .and_then(|mut stmt| stmt.query_map(params, |row| row.get(0))?.collect())
+            .map_err(self.err())
+    }
+}
+
+/// Hash, source and stamp of all files
+fn file_rows(conn: &Connection) -> rusqlite::Result<Vec<(String, String, Option<String>)>> {
+    conn.prepare("SELECT hash, source, stamp FROM files ORDER BY hash")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect()
+}
+
+/// Ranges and data of all spans, grouped by file hash
+fn span_rows(conn: &Connection) -> rusqlite::Result<IndexMap<String, Vec<(String, String)>>> {
+    let mut spans: IndexMap<String, Vec<(String, String)>> = IndexMap::new();
+    let mut stmt = conn.prepare("SELECT file, range, data FROM spans ORDER BY rowid")?;
+    for row in stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))? {
+        let (file, range, data) = row?;
+        spans.entry(file).or_default().push((range, data));
+    }
+
+    Ok(spans)
+}
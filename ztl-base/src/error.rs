@@ -1,6 +1,8 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::ops::Range;
 use std::fmt;
-use serde::Serialize;
+use indexmap::IndexMap;
+use serde::{Serialize, Serializer, ser::SerializeStruct};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -18,6 +20,24 @@ pub enum Error {
     InvalidNote(PathBuf, toml::de::Error),
     #[error("invalid span file")]
     InvalidFileSpan(PathBuf, toml::de::Error),
+    #[error("no parser registered for {}", .0.display())]
+    UnknownFormat(PathBuf),
+    #[error("unknown citation style `{0}`")]
+    UnknownStyle(String),
+    #[error("no bibliography entry with key `{0}`")]
+    UnknownEntry(String),
+    #[error("no note with key `{0}`")]
+    UnknownNote(String),
+    #[error("could not parse bibliography {path}: {1}", path = .0.display())]
+    InvalidBibliography(PathBuf, String),
+    #[error("bibliography entry `{0}` cannot be formatted: {1}")]
+    UnformattableEntry(String, String),
+    #[error("could not read Zotero database {path}: {1}", path = .0.display())]
+    Zotero(PathBuf, String),
+    #[error("could not access cache database {path}: {1}", path = .0.display())]
+    Database(PathBuf, String),
+    #[error("cache backend `{0}` is not enabled in this build")]
+    UnsupportedBackend(String),
     #[error("{0}")]
     Parse(ParseReport),
 }
@@ -31,6 +51,15 @@ impl Error {
             Error::InputOutput(x) => ErrorSer::InputOutput(x.to_string()),
             Error::InvalidNote(p, x) => ErrorSer::InvalidNote(p, x.to_string()),
             Error::InvalidFileSpan(p, x) => ErrorSer::InvalidFileSpan(p, x.to_string()),
+            Error::UnknownFormat(p) => ErrorSer::UnknownFormat(p),
+            Error::UnknownStyle(x) => ErrorSer::UnknownStyle(x),
+            Error::UnknownEntry(x) => ErrorSer::UnknownEntry(x),
+            Error::UnknownNote(x) => ErrorSer::UnknownNote(x),
+            Error::InvalidBibliography(p, x) => ErrorSer::InvalidBibliography(p, x),
+            Error::UnformattableEntry(k, x) => ErrorSer::UnformattableEntry(k, x),
+            Error::Zotero(p, x) => ErrorSer::Zotero(p, x),
+            Error::Database(p, x) => ErrorSer::Database(p, x),
+            Error::UnsupportedBackend(x) => ErrorSer::UnsupportedBackend(x),
             Error::Parse(x) => ErrorSer::Parse(x),
         }
     }
@@ -44,73 +73,358 @@ pub enum ErrorSer {
     InputOutput(String),
     InvalidNote(PathBuf, String),
     InvalidFileSpan(PathBuf, String),
+    UnknownFormat(PathBuf),
+    UnknownStyle(String),
+    UnknownEntry(String),
+    UnknownNote(String),
+    InvalidBibliography(PathBuf, String),
+    UnformattableEntry(String, String),
+    Zotero(PathBuf, String),
+    Database(PathBuf, String),
+    UnsupportedBackend(String),
     Parse(ParseReport),
 }
 
-#[derive(Debug, Serialize)]
+/// Severity of a diagnostic, only errors fail a build by default
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+/// Stable code of a diagnostic
+///
+/// Codes starting with `E` are errors and `W` warnings. Codes are never
+/// reused, so that tools can rely on them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Code {
+    /// Link target is not a known note
+    InvalidReference,
+    /// Anchor of link is not defined in the target
+    UnknownAnchor,
+    /// Page of link exceeds the pages of the target
+    PageOutOfRange,
+    /// View modifier of link cannot be parsed
+    InvalidView,
+    /// Linked file does not exist
+    MissingFile,
+    /// Heading with key, but no header
+    MissingHeader,
+    /// Heading skips a level
+    HeadingLevel,
+    /// Front matter is neither valid TOML nor YAML
+    InvalidFrontMatter,
+    /// LaTeX environment is never closed
+    UnclosedEnvironment,
+    /// Note could not be rendered by an external tool
+    RenderFailed,
+    /// BibTeX file cannot be parsed
+    InvalidBibtex,
+    /// CSL-JSON file or item is malformed
+    InvalidCslJson,
+    /// RIS record is malformed
+    InvalidRis,
+    /// Key is defined by more than one note
+    DuplicateKey,
+    /// Note has an empty header
+    EmptyHeader,
+    /// Bibliography entry is never referenced
+    UnusedEntry,
+    /// Note links to itself
+    SelfLink,
+    /// Bibliography entry is parsed, but cannot be formatted with CSL style
+    UnformattableEntry,
+}
+
+impl Code {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Code::InvalidReference => "E001",
+            Code::UnknownAnchor => "E002",
+            Code::PageOutOfRange => "E003",
+            Code::InvalidView => "E004",
+            Code::MissingFile => "E005",
+            Code::MissingHeader => "E101",
+            Code::HeadingLevel => "E102",
+            Code::InvalidFrontMatter => "E103",
+            Code::UnclosedEnvironment => "E104",
+            Code::RenderFailed => "E105",
+            Code::InvalidBibtex => "E106",
+            Code::InvalidCslJson => "E107",
+            Code::InvalidRis => "E108",
+            Code::DuplicateKey => "E109",
+            Code::EmptyHeader => "W001",
+            Code::UnusedEntry => "W002",
+            Code::SelfLink => "W003",
+            Code::UnformattableEntry => "W004",
+        }
+    }
+
+    pub fn severity(&self) -> Severity {
+        match self {
+            Code::EmptyHeader | Code::UnusedEntry | Code::SelfLink | Code::UnformattableEntry => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+
+    /// Default help text, explaining how to resolve the diagnostic
+    fn help(&self) -> Option<&'static str> {
+        match self {
+            Code::InvalidReference => Some("check the key of the target or create a note with this key"),
+            Code::UnknownAnchor => Some("run `ztl anchors <key>` to list the anchors of the target"),
+            Code::InvalidView => Some("supported modifiers are `anchor`, `page`, `pagelabel`, `search`, `lines` and `t`"),
+            Code::MissingHeader => Some("add a header after the key, e.g. `# key Header`"),
+            Code::DuplicateKey => Some("rename one of the notes, the definition in the first file by path is kept"),
+            Code::EmptyHeader => Some("add a header, so that the note can be found and linked without label"),
+            Code::UnusedEntry => Some("cite the entry in a note or remove it from the bibliography"),
+            Code::SelfLink => Some("link to an anchor of the note with `key#anchor` instead"),
+            Code::UnformattableEntry => Some("check the fields of the entry (e.g. dates and numbers), the note has no formatted reference"),
+            _ => None,
+        }
+    }
+}
+
+impl Serialize for Code {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+/// Collection of diagnostics
+///
+/// Serialized diagnostics carry the byte ranges of note and reference in
+/// their source files, so that tools don't need to recompute them.
+#[derive(Debug)]
 pub struct ParseReport {
-    inner: Vec<Span>,
+    inner: Vec<Diagnostic>,
+    /// Directory note sources are relative to
+    root: PathBuf,
 }
 
 impl ParseReport {
     pub fn empty() -> Self {
-        ParseReport { inner: Vec::new() }
+        ParseReport { inner: Vec::new(), root: PathBuf::new() }
     }
 
-    pub fn new(note: &crate::Span, reference: &crate::Span, reason: &str) -> ParseReport {
+    /// Empty report, which reads sources relative to the repository root
+    pub fn in_root(root: &Path) -> Self {
+        ParseReport { inner: Vec::new(), root: root.to_path_buf() }
+    }
+
+    /// Report problem at reference within note, the severity follows from
+    /// the code
+    pub fn new(note: &crate::Span, reference: &crate::Span, code: Code, reason: &str) -> ParseReport {
         ParseReport {
-            inner: vec![Span { note: note.clone(), reference: reference.clone(), reason: reason.to_string() }]
+            inner: vec![Diagnostic {
+                note: note.clone(),
+                reference: reference.clone(),
+                reason: reason.to_string(),
+                severity: code.severity(),
+                code,
+                help: code.help().map(|x| x.to_string()),
+            }],
+            root: PathBuf::new(),
+        }
+    }
+
+    /// Replace the default help text of all diagnostics
+    pub fn with_help(mut self, help: &str) -> ParseReport {
+        for span in &mut self.inner {
+            span.help = Some(help.to_string());
         }
+
+        self
     }
 
     pub fn append(&mut self, report: ParseReport) {
         self.inner.extend(report.inner)
     }
 
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.inner.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Number of diagnostics with severity
+    pub fn count(&self, severity: Severity) -> usize {
+        self.inner.iter().filter(|x| x.severity == severity).count()
+    }
+
+    /// Fail with the whole report, if it contains any error
+    ///
+    /// Remaining warnings and notes are dropped, use [`ParseReport::check`]
+    /// to keep them.
     pub fn as_err(self) -> Result<()> {
-        if self.inner.len() == 0 {
-            return Ok(());
-        } else {
+        self.check(false).map(|_| ())
+    }
+
+    /// Fail with the whole report, if it contains any error or warnings are
+    /// denied, otherwise return the remaining diagnostics
+    pub fn check(self, deny_warnings: bool) -> Result<ParseReport> {
+        if self.count(Severity::Error) > 0 || (deny_warnings && self.count(Severity::Warning) > 0) {
             Err(Error::Parse(self))
+        } else {
+            Ok(self)
         }
     }
 }
 
+/// Problem at a reference within a note
 #[derive(Debug, Serialize)]
-struct Span {
-    pub(crate) note: crate::Span,
-    pub(crate) reference: crate::Span,
-    pub(crate) reason: String,
+pub struct Diagnostic {
+    pub note: crate::Span,
+    pub reference: crate::Span,
+    pub reason: String,
+    pub severity: Severity,
+    pub code: Code,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub help: Option<String>,
 }
 
+impl Diagnostic {
+    /// Source of the note, spans without source are part of the same file
+    /// as the reference and vice versa
+    pub fn note_source(&self) -> Option<&PathBuf> {
+        self.note.source.as_ref().or(self.reference.source.as_ref())
+    }
+
+    pub fn reference_source(&self) -> Option<&PathBuf> {
+        self.reference.source.as_ref().or(self.note.source.as_ref())
+    }
+
+    /// Byte range of the note, up to the end of its last line
+    ///
+    /// Without column, the end line is the index of the last line as in
+    /// note spans.
+    fn note_range(&self, content: &str) -> Range<usize> {
+        let end = match self.note.end.column {
+            Some(column) => (self.note.end.line, column),
+            None => (self.note.end.line + 1, usize::MAX),
+        };
+
+        clamped_range(content, (self.note.start.line, self.note.start.column.unwrap_or(1)), end)
+    }
+
+    /// Byte range of the reference, without end column up to the end of line
+    fn reference_range(&self, content: &str) -> Range<usize> {
+        clamped_range(content,
+            (self.reference.start.line, self.reference.start.column.unwrap_or(1)),
+            (self.reference.end.line, self.reference.end.column.unwrap_or(usize::MAX)))
+    }
+}
+
+/// Byte range between two positions, which are clamped to the content
+fn clamped_range(content: &str, start: (usize, usize), end: (usize, usize)) -> Range<usize> {
+    use crate::utils::line_col_to_byte_offset as offset;
+
+    // positions after the last line are moved to the end of content
+    let start = offset(content, start.0.max(1), start.1.max(1)).unwrap_or(content.len());
+    let end = offset(content, end.0.max(1), end.1.max(1)).unwrap_or(content.len());
+
+    start..end.max(start)
+}
+
+/// Contents of all files referred to by a report, `None` if a file cannot
+/// be read (e.g. removed since or binary)
+fn sources<'a>(spans: &'a [Diagnostic], root: &Path) -> IndexMap<&'a Path, Option<String>> {
+    spans.iter()
+        .flat_map(|x| [x.note_source(), x.reference_source()])
+        .flatten()
+        .map(|x| (x.as_path(), std::fs::read_to_string(root.join(x)).ok()))
+        .collect()
+}
+
+impl Serialize for ParseReport {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct WithRanges<'a> {
+            #[serde(flatten)]
+            span: &'a Diagnostic,
+            note_range: Option<Range<usize>>,
+            reference_range: Option<Range<usize>>,
+        }
+
+        let sources = sources(&self.inner, &self.root);
+        let content = |path: Option<&PathBuf>| path
+            .and_then(|x| sources.get(x.as_path()))
+            .and_then(|x| x.as_deref());
+
+        let inner = self.inner.iter().map(|span| WithRanges {
+            span,
+            note_range: content(span.note_source()).map(|x| span.note_range(x)),
+            reference_range: content(span.reference_source()).map(|x| span.reference_range(x)),
+        }).collect::<Vec<_>>();
+
+        let mut state = serializer.serialize_struct("ParseReport", 1)?;
+        state.serialize_field("inner", &inner)?;
+        state.end()
+    }
+}
 
 impl fmt::Display for ParseReport {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use annotate_snippets::renderer::DecorStyle;
-        use annotate_snippets::{AnnotationKind, Level, Renderer, Snippet};
-        use crate::utils::line_col_to_byte_offset as offset;
+        use annotate_snippets::{AnnotationKind, Group, Level, Renderer, Snippet};
+
+        let sources = sources(&self.inner, &self.root);
+        let content = |path: Option<&PathBuf>| path
+            .and_then(|x| sources.get(x.as_path()))
+            .and_then(|x| x.as_ref().map(|content| (x, content)));
 
         let report = self.inner.iter().map(|span| {
-            let binding = span.note.source.clone().unwrap();
-            let binding = binding.to_str().unwrap().to_string();
-
-            let content = std::fs::read_to_string(span.note.source.as_ref().unwrap()).unwrap();
-
-            let header_start = offset(&content, span.note.start.line, span.note.start.column.unwrap_or(1)).unwrap();
-            let header_end = offset(&content, span.note.end.line, span.note.end.column.unwrap_or(1)).unwrap();
-
-            let offset_start = offset(&content, span.reference.start.line, span.reference.start.column.unwrap_or(1)).unwrap();
-            let offset_end = offset(&content, span.reference.end.line, span.reference.end.column.unwrap_or(200)).unwrap();
-
-            Level::ERROR
-                .primary_title("could not parse note")
-                .element(
-                    Snippet::source(content).path(binding)
-                    .annotation(AnnotationKind::Primary.span(offset_start..offset_end)
-                            .label(&span.reason))
-                    .annotation(AnnotationKind::Context.span(header_start..header_end))
-                )
-            }).collect::<Vec<_>>();
+            let (level, title) = match span.severity {
+                Severity::Error => (Level::ERROR, "could not parse note"),
+                Severity::Warning => (Level::WARNING, "note may contain a mistake"),
+                Severity::Note => (Level::NOTE, "note could be improved"),
+            };
+
+            let mut group = Group::with_title(level.primary_title(title).id(span.code.as_str()));
+
+            let reference = span.reference_source().zip(content(span.reference_source()));
+            let note = span.note_source().zip(content(span.note_source()));
+
+            match (reference, note) {
+                // note and reference in the same file
+                (Some((reference_path, (_, content))), Some((note_path, _))) if reference_path == note_path => {
+                    group = group.element(Snippet::source(content.as_str())
+                        .path(reference_path.display().to_string())
+                        .annotation(AnnotationKind::Primary.span(span.reference_range(content)).label(&span.reason))
+                        .annotation(AnnotationKind::Context.span(span.note_range(content))));
+                },
+                // e.g. note with error in preamble, the cause is shown first
+                (reference, note) => {
+                    if let Some((path, (_, content))) = reference {
+                        group = group.element(Snippet::source(content.as_str())
+                            .path(path.display().to_string())
+                            .annotation(AnnotationKind::Primary.span(span.reference_range(content)).label(&span.reason)));
+                    } else {
+                        let location = match span.reference_source() {
+                            Some(x) if span.reference.has_lines() => format!("{}:{}: ", x.display(), span.reference.start.line),
+                            Some(x) => format!("{}: ", x.display()),
+                            None => String::new(),
+                        };
+
+                        group = group.element(Level::NOTE.message(format!("{}{}", location, span.reason)));
+                    }
+
+                    if let Some((path, (_, content))) = note {
+                        group = group.element(Snippet::source(content.as_str())
+                            .path(path.display().to_string())
+                            .annotation(AnnotationKind::Context.span(span.note_range(content))));
+                    }
+                },
+            }
+
+            match &span.help {
+                Some(help) => group.element(Level::HELP.message(help)),
+                None => group,
+            }
+        }).collect::<Vec<_>>();
 
         let renderer = Renderer::styled().decor_style(DecorStyle::Unicode);
         write!(f, "{}", renderer.render(&report))
@@ -9,11 +9,15 @@ pub mod parser;
 #[cfg(feature = "htmlrender")]
 pub mod tera;
 
-use std::fs;
+#[cfg(feature = "sqlite")]
+pub mod database;
+
+use std::{fmt, fs};
+use std::str::FromStr;
 use std::path::{Path, PathBuf};
 use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
-use error::{Result, ParseReport};
+use error::{Result, ParseReport, Code};
 
 /// Note key (any length, any character)
 pub type Key = String;
@@ -27,12 +31,198 @@ pub struct Outgoing {
     pub comment: String,
     /// Displayed label of the link
     pub label: String,
-    /// View modifiers (such as page number, anchor, search pattern etc.)
-    pub view: IndexMap<String, String>,
+    /// View modifier (such as page number, anchor, search pattern etc.)
+    #[serde(default, deserialize_with = "deserialize_view")]
+    pub view: Option<View>,
+    /// Span information, where to find the link in source
+    pub span: Span,
+}
+
+/// View modifier of a link, following the target key after `#`
+///
+/// Modifiers are written as `key#name=value`, a modifier without value is an
+/// anchor. Page and search modifiers follow the PDF open parameters, media
+/// timestamps the media fragments of W3C, so that they can be passed on to
+/// viewers of resources.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum View {
+    /// Named anchor, e.g. `#sec.intro` or `#anchor=sec.intro`
+    Anchor(String),
+    /// Physical page, starting at one, e.g. `#page=3`
+    Page(usize),
+    /// Printed page label, e.g. `#pagelabel=iv`
+    PageLabel(String),
+    /// Text to search for, e.g. `#search=gradient descent`
+    Search(String),
+    /// Inclusive range of lines, e.g. `#lines=10-20` or `#line=10`
+    Lines { start: usize, end: usize },
+    /// Media timestamp in seconds with optional end, e.g. `#t=90` or
+    /// `#t=1:30,2:00`
+    Time { start: f64, end: Option<f64> },
+}
+
+impl View {
+    /// Human readable description, e.g. `p. 3` or `Sec intro`
+    pub fn describe(&self) -> String {
+        match self {
+            View::Anchor(anchor) => {
+                let mut anchor = anchor.replacen(".", " ", 1);
+                if let Some(r) = anchor.get_mut(0..1) {
+                    r.make_ascii_uppercase();
+                }
+                anchor
+            },
+            View::Page(page) => format!("p. {}", page),
+            View::PageLabel(label) => format!("p. {}", label),
+            View::Search(text) => format!("\"{}\"", text),
+            View::Lines { start, end } if start == end => format!("l. {}", start),
+            View::Lines { start, end } => format!("ll. {}–{}", start, end),
+            View::Time { start, end: None } => timestamp(*start),
+            View::Time { start, end: Some(end) } => format!("{}–{}", timestamp(*start), timestamp(*end)),
+        }
+    }
+
+    /// Check view against the anchors and pages of the target note, returns
+    /// the problem if any
+    ///
+    /// Anchors are only checked, if the target knows all of its anchors.
+    pub fn check(&self, target: &Note) -> Option<(Code, String)> {
+        match (self, &target.anchors, target.pages) {
+            (View::Anchor(anchor), Some(anchors), _) if !anchors.contains(anchor) =>
+                Some((Code::UnknownAnchor, format!("unknown anchor `{}` in `{}`", anchor, target.id))),
+            (View::Page(page), _, Some(pages)) if *page > pages =>
+                Some((Code::PageOutOfRange, format!("page {} out of range, `{}` has {} pages", page, target.id, pages))),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for View {
+    type Err = String;
+
+    fn from_str(modifier: &str) -> std::result::Result<Self, Self::Err> {
+        if modifier.contains("#") {
+            return Err(format!("multiple view modifiers in `{}`, only one is supported", modifier));
+        }
+
+        let (name, value) = match modifier.split_once("=") {
+            Some((name, value)) => (name.trim(), value.trim()),
+            None => ("anchor", modifier.trim()),
+        };
+
+        if value.is_empty() {
+            return Err(format!("view modifier `{}` without value", name));
+        }
+
+        let number = |x: &str| x.trim().parse::<usize>().ok().filter(|x| *x > 0);
+        let invalid = || match name {
+            "line" | "lines" => format!("invalid line range `{}`", value),
+            "t" | "time" => format!("invalid timestamp `{}`", value),
+            _ => format!("invalid {} `{}`", name, value),
+        };
+
+        match name {
+            "anchor" => Ok(View::Anchor(value.to_string())),
+            "page" => number(value).map(View::Page).ok_or_else(invalid),
+            "pagelabel" => Ok(View::PageLabel(value.to_string())),
+            "search" => Ok(View::Search(value.to_string())),
+            "line" | "lines" => {
+                let (start, end) = value.split_once("-").unwrap_or((value, value));
+                match (number(start), number(end)) {
+                    (Some(start), Some(end)) if start <= end => Ok(View::Lines { start, end }),
+                    _ => Err(invalid()),
+                }
+            },
+            "t" | "time" => {
+                let (start, end) = match value.split_once(",") {
+                    Some((start, end)) => (seconds(start), Some(seconds(end))),
+                    None => (seconds(value), None),
+                };
+                match (start, end) {
+                    (Some(start), None) => Ok(View::Time { start, end: None }),
+                    (Some(start), Some(Some(end))) if start <= end => Ok(View::Time { start, end: Some(end) }),
+                    _ => Err(invalid()),
+                }
+            },
+            _ => Err(format!("unknown view modifier `{}`", name)),
+        }
+    }
+}
+
+impl fmt::Display for View {
+    /// Modifier as written after `#`, which is parsed to the same view
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            View::Anchor(anchor) => write!(f, "{}", anchor),
+            View::Page(page) => write!(f, "page={}", page),
+            View::PageLabel(label) => write!(f, "pagelabel={}", label),
+            View::Search(text) => write!(f, "search={}", text),
+            View::Lines { start, end } => write!(f, "lines={}-{}", start, end),
+            View::Time { start, end: None } => write!(f, "t={}", start),
+            View::Time { start, end: Some(end) } => write!(f, "t={},{}", start, end),
+        }
+    }
+}
+
+/// View of a link, also accepts modifiers as map of strings, as written by
+/// previous versions of the cache
+fn deserialize_view<'de, D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Option<View>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Stored {
+        View(View),
+        Modifiers(IndexMap<String, String>),
+    }
+
+    Ok(match Option::<Stored>::deserialize(deserializer)? {
+        Some(Stored::View(view)) => Some(view),
+        Some(Stored::Modifiers(map)) => map.into_iter().next()
+            .and_then(|(k, v)| format!("{}={}", k, v).parse().ok()),
+        None => None,
+    })
+}
+
+/// Seconds of timestamp in the form `[[hh:]mm:]ss[.fraction]`
+fn seconds(timestamp: &str) -> Option<f64> {
+    let parts = timestamp.trim().split(":").collect::<Vec<_>>();
+    if parts.len() > 3 || parts.iter().any(|x| x.is_empty()) {
+        return None;
+    }
+
+    parts.iter().try_fold(0.0, |acc, x| Some(acc * 60.0 + x.parse::<f64>().ok().filter(|x| *x >= 0.0)?))
+}
+
+/// Timestamp as `m:ss` or `h:mm:ss`, fractions of seconds are dropped
+fn timestamp(seconds: f64) -> String {
+    let seconds = seconds as u64;
+    match seconds / 3600 {
+        0 => format!("{}:{:02}", seconds / 60, seconds % 60),
+        hours => format!("{}:{:02}:{:02}", hours, seconds / 60 % 60, seconds % 60),
+    }
+}
+
+/// Link to a web page, mail address or file, which is not a note
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct External {
+    pub kind: LinkKind,
+    /// URL, mail address or file path relative to ZTL root
+    pub target: String,
+    /// Displayed label of the link
+    pub label: String,
     /// Span information, where to find the link in source
     pub span: Span,
 }
 
+/// Kind of link target, which is not a note
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkKind {
+    Url,
+    File,
+    Mailto,
+}
+
 /// Location in a file
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
 pub struct LineColumn {
@@ -53,6 +243,19 @@ pub struct Span {
     pub end: LineColumn
 }
 
+impl Span {
+    /// Span of a source without lines (e.g. entry of a database), its start
+    /// and end line are zero
+    pub fn without_lines(source: &Path) -> Span {
+        Span { source: Some(source.to_path_buf()), ..Default::default() }
+    }
+
+    /// Whether the span refers to lines of its source, these start at one
+    pub fn has_lines(&self) -> bool {
+        self.start.line > 0
+    }
+}
+
 /// Note entry
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Note {
@@ -63,6 +266,9 @@ pub struct Note {
     #[serde(default)]
     pub children: Vec<Key>,
     pub outgoing: Vec<Outgoing>,
+    /// Links to web pages, mail addresses and files
+    #[serde(default)]
+    pub external: Vec<External>,
     pub incoming: Vec<Key>,
     pub html: String,
     pub span: Span,
@@ -72,6 +278,47 @@ pub struct Note {
     pub public: bool,
     #[serde(default)]
     pub cards: Vec<Card>,
+    /// Additional metadata attached to the note (e.g. org properties)
+    #[serde(default)]
+    pub metadata: IndexMap<String, toml::Value>,
+    /// Bibliographic metadata of literature notes
+    #[serde(default)]
+    pub reference: Option<Reference>,
+    /// Anchors exposed to links (e.g. LaTeX labels, sub-headings, named
+    /// destinations of PDF resources), not validated if unknown
+    #[serde(default)]
+    pub anchors: Option<Vec<String>>,
+    /// Number of pages of PDF resource
+    #[serde(default)]
+    pub pages: Option<usize>,
+    /// Stamp of PDF resource, its named destinations are only read again if
+    /// the file changed
+    #[serde(default)]
+    pub pdf: Option<Stamp>,
+}
+
+/// Bibliographic information, e.g. from a BibTeX entry
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct Reference {
+    /// Authors in display form (e.g. "J. R. R. Tolkien")
+    #[serde(default)]
+    pub authors: Vec<String>,
+    pub year: Option<i32>,
+    pub doi: Option<String>,
+    pub journal: Option<String>,
+    #[serde(rename = "abstract")]
+    pub abstract_: Option<String>,
+    #[serde(default)]
+    pub keywords: Vec<String>,
+}
+
+impl Reference {
+    /// Check whether any author contains the name (case insensitive)
+    pub fn has_author(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+
+        self.authors.iter().any(|x| x.to_lowercase().contains(&name))
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -86,9 +333,50 @@ pub struct NodeOutgoing {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct File {
     source: PathBuf,
+    /// Missing for files, which have to be parsed again on next update
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stamp: Option<Stamp>,
     spans: IndexMap<String, FileSpan>,
 }
 
+/// Size, modification time and content hash of a source file
+///
+/// Files with the same content and dependencies are not parsed again.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Stamp {
+    size: u64,
+    /// Modification time in nanoseconds since the epoch
+    mtime: u64,
+    hash: String,
+    /// Fingerprint of the parser dependencies and configuration
+    dependencies: String,
+}
+
+impl Stamp {
+    /// Stamp of file on disk, the content is only hashed again if size or
+    /// modification time differ from the previous stamp
+    pub(crate) fn of(path: &Path, dependencies: &str, previous: Option<&Stamp>) -> Option<Stamp> {
+        let metadata = fs::metadata(path).ok()?;
+        let size = metadata.len();
+        let mtime = metadata.modified().ok()?
+            .duration_since(std::time::UNIX_EPOCH).ok()?
+            .as_nanos() as u64;
+
+        let hash = match previous {
+            Some(x) if x.size == size && x.mtime == mtime => x.hash.clone(),
+            _ => utils::hash(fs::read(path).ok()?),
+        };
+
+        Some(Stamp { size, mtime, hash, dependencies: dependencies.to_string() })
+    }
+
+    /// Whether content and dependencies are unchanged, the modification time
+    /// may differ (e.g. file saved without changes)
+    pub(crate) fn unchanged(&self, other: &Stamp) -> bool {
+        self.hash == other.hash && self.dependencies == other.dependencies
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct FileSpan {
     target: Key,
@@ -118,11 +406,19 @@ impl Note {
         self.hash.clone()
     }
 
-    pub(crate) fn is_tex(&self) -> bool {
-        self.span.source.as_ref().map(|x| x.to_str().unwrap().ends_with(".tex")).unwrap_or(false)
-    }
+    pub(crate) fn outgoing_spans(&self, notes: &notes::Notes, root: &Path, report: &mut ParseReport) -> Result<FileSpan> {
+        // titles of literature notes are up to the bibliography
+        if self.header.trim().is_empty() && self.reference.is_none() {
+            let header = Span {
+                source: None,
+                start: LineColumn { line: self.span.start.line, column: None },
+                end: LineColumn { line: self.span.start.line, column: None },
+            };
+
+            report.append(error::ParseReport::new(&self.span, &header, Code::EmptyHeader,
+                &format!("note `{}` has an empty header", self.id)));
+        }
 
-    pub(crate) fn outgoing_spans(&self, notes: &notes::Notes, report: &mut ParseReport) -> Result<FileSpan> {
         self.outgoing.iter().enumerate().filter_map(|(idx, s)| {
             let key = format!("{}:{},{}:{}", s.span.start.line,s.span.start.column.unwrap_or(1),s.span.end.line,s.span.end.column.unwrap_or(1));
 
@@ -132,22 +428,21 @@ impl Note {
                     report.append(error::ParseReport::new(
                         &self.span,
                         &s.span,
+                        Code::InvalidReference,
                         "invalid reference"));
 
                     return None;
                 }
             };
 
-            let mut view = None;
-            if let Some(anchor) = s.view.get("anchor") {
-                let mut anchor = anchor.replacen(".", " ", 1);
-                if let Some(r) = anchor.get_mut(0..1) {
-                    r.make_ascii_uppercase();
-                }
-                view = Some(anchor);
+            // links to an anchor of the note itself are fine
+            if target_note.id == self.id && s.view.is_none() {
+                report.append(error::ParseReport::new(&self.span, &s.span, Code::SelfLink,
+                    &format!("note `{}` links to itself", self.id)));
             }
-            if let Some(page) = s.view.get("page") {
-                view = Some(format!("p. {}", page));
+
+            if let Some((code, problem)) = s.view.as_ref().and_then(|x| x.check(target_note)) {
+                report.append(error::ParseReport::new(&self.span, &s.span, code, &problem));
             }
 
             let target_node = NodeOutgoing {
@@ -155,11 +450,24 @@ impl Note {
                 header: target_note.header.clone(),
                 source: target_note.span.source.as_ref().unwrap().display().to_string(),
                 index: idx,
-                view,
+                view: s.view.as_ref().map(View::describe),
             };
 
             Some(Ok((key, target_node)))
-        }).collect::<Result<IndexMap<_, _>>>().map(|spans|
+        }).collect::<Result<IndexMap<_, _>>>().map(|spans| {
+            // file links are relative to the repository root
+            for link in self.external.iter().filter(|x| x.kind == LinkKind::File) {
+                if !root.join(&link.target).exists() {
+                    report.append(error::ParseReport::new(
+                        &self.span,
+                        &link.span,
+                        Code::MissingFile,
+                        &format!("file `{}` does not exist", link.target)));
+                }
+            }
+
+            spans
+        }).map(|spans|
             FileSpan {
                 target: self.id.clone(), header: self.header.clone(), kind: self.kind.clone(), outgoing: spans
             })
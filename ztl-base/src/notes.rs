@@ -1,10 +1,10 @@
 use std::fs;
 use std::path::Path;
-use indexmap::IndexMap;
+use indexmap::{IndexMap, IndexSet};
 use std::io::Write;
 use std::fmt;
 
-use crate::{*, error::{Result, Error}};
+use crate::{*, config::{Config, CacheBackend}, error::{Result, Error, ParseReport, Code}};
 
 /// Collection of notes and associated files
 #[derive(Debug, Clone)]
@@ -19,7 +19,8 @@ pub enum Change {
     NoteAdded(Key),
     NoteRemoved(Key, Note),
     NoteMoved(Key, PathBuf, PathBuf),
-    NoteChanged(Key),
+    /// Note with modified metadata fields (e.g. title of a BibTeX entry)
+    NoteChanged(Key, Vec<String>),
     FileAdded(PathBuf),
     FileRemoved(PathBuf),
 }
@@ -36,6 +37,7 @@ pub enum NoteChange {
     Add,
     Remove, 
     Modified(Option<PathBuf>),
+    Fields(Vec<String>),
 }
 
 #[derive(Serialize, Debug)]
@@ -65,9 +67,13 @@ impl fmt::Display for Changes {
                  match change {
                      NoteChange::Add => write!(f, "+")?,
                      NoteChange::Remove => write!(f, "-")?,
-                     NoteChange::Modified(_) => write!(f, " ")?,
+                     NoteChange::Modified(_) | NoteChange::Fields(_) => write!(f, " ")?,
                  }
-                 write!(f, "{} {}\n", id, header)?;
+                 write!(f, "{} {}", id, header)?;
+                 if let NoteChange::Fields(fields) = change {
+                     write!(f, " ({})", fields.join(", "))?;
+                 }
+                 write!(f, "\n")?;
              }
              
          }
@@ -113,6 +119,68 @@ impl Notes {
         Ok(Self { notes, files, changes: Vec::new() })
     }
 
+    /// Read notes from the cache backend of the configuration
+    pub fn load(config: &Config) -> Result<Self> {
+        match config.cache.backend {
+            CacheBackend::Toml => Self::from_cache(&config.ztl_root()),
+            #[cfg(feature = "sqlite")]
+            CacheBackend::Sqlite => crate::database::Database::open(&config.ztl_root())?.load(),
+            #[cfg(not(feature = "sqlite"))]
+            CacheBackend::Sqlite => Err(Error::UnsupportedBackend("sqlite".to_string())),
+        }
+    }
+
+    /// Read only notes with given keys from the cache backend, unknown keys
+    /// are skipped and no file spans are read
+    pub fn load_keys(config: &Config, keys: &[Key]) -> Result<Self> {
+        let notes = match config.cache.backend {
+            CacheBackend::Toml => keys.iter()
+                .map(|x| config.ztl_root().join("notes").join(x))
+                .filter(|x| x.exists())
+                .map(|x| {
+                    let content = std::fs::read_to_string(&x)?;
+
+                    toml::from_str(&content).map_err(|err| Error::InvalidNote(x, err))
+                })
+                .collect::<Result<Vec<Note>>>()?,
+            #[cfg(feature = "sqlite")]
+            CacheBackend::Sqlite => {
+                let database = crate::database::Database::open(&config.ztl_root())?;
+
+                keys.iter()
+                    .filter_map(|x| database.note(x).transpose())
+                    .collect::<Result<Vec<Note>>>()?
+            },
+            #[cfg(not(feature = "sqlite"))]
+            CacheBackend::Sqlite => return Err(Error::UnsupportedBackend("sqlite".to_string())),
+        };
+
+        let notes = notes.into_iter()
+            .map(|x| (x.id.clone(), x))
+            .collect();
+
+        Ok(Self { notes, files: IndexMap::new(), changes: Vec::new() })
+    }
+
+    /// Write notes to the cache backend of the configuration, TOML files are
+    /// written as well if exported
+    pub fn store(&self, config: &Config) -> Result<()> {
+        match config.cache.backend {
+            CacheBackend::Toml => self.write_to_cache(&config.ztl_root()),
+            #[cfg(feature = "sqlite")]
+            CacheBackend::Sqlite => {
+                crate::database::Database::open(&config.ztl_root())?.write(self)?;
+
+                match config.cache.export {
+                    true => self.write_to_cache(&config.ztl_root()),
+                    false => Ok(()),
+                }
+            },
+            #[cfg(not(feature = "sqlite"))]
+            CacheBackend::Sqlite => Err(Error::UnsupportedBackend("sqlite".to_string())),
+        }
+    }
+
     pub fn empty() -> Self {
         Self {
             notes: IndexMap::new(),
@@ -121,13 +189,25 @@ impl Notes {
         }
     }
 
+    /// Forget the stamps of all files, so that they are parsed again on the
+    /// next update
+    pub fn forget_stamps(&mut self) {
+        for file in self.files.values_mut() {
+            file.stamp = None;
+        }
+    }
+
     /// Update incoming links in notes
     ///
     /// Collect outgoing and parent links, and distribute
-    /// to incoming and children attributes
+    /// to incoming and children attributes. Links without label
+    /// fall back to the header of their target.
     pub fn update_incoming_links(&mut self) {
         let mut incoming: IndexMap<Key, Vec<Key>> = IndexMap::new();
         let mut children: IndexMap<Key, Vec<Key>> = IndexMap::new();
+        let headers = self.notes.values()
+            .map(|x| (x.id.clone(), x.header.clone()))
+            .collect::<IndexMap<_, _>>();
 
         for note in self.notes.values() {
             for link in &note.outgoing {
@@ -149,6 +229,39 @@ impl Notes {
             if children.contains_key(&note.id) {
                 note.children = children.get(&note.id).unwrap().clone();
             }
+
+            if note.html.contains(FALLBACK) {
+                note.html = fill_fallback_labels(&note.html, &headers);
+            }
+        }
+    }
+
+    /// Report entries of BibTeX files, which are not the target of any link
+    ///
+    /// Libraries of reference managers usually contain many more entries than
+    /// cited, hence only BibTeX files are checked.
+    pub fn unused_entries(&self, report: &mut ParseReport) {
+        let targets = self.notes.values()
+            .flat_map(|x| x.outgoing.iter().map(|x| x.target.as_str()))
+            .collect::<IndexSet<_>>();
+
+        for note in self.notes.values() {
+            let is_bibtex = note.span.source.as_ref()
+                .and_then(|x| x.extension())
+                .map(|x| x == "bib").unwrap_or(false);
+
+            if !is_bibtex || targets.contains(note.id.as_str()) {
+                continue;
+            }
+
+            let entry = Span {
+                source: None,
+                start: LineColumn { line: note.span.start.line, column: None },
+                end: LineColumn { line: note.span.start.line, column: None },
+            };
+
+            report.append(ParseReport::new(&note.span, &entry, Code::UnusedEntry,
+                &format!("bibliography entry `{}` is never cited", note.id)));
         }
     }
 
@@ -218,14 +331,19 @@ impl Notes {
                     inner.1.entry(note.id.clone()).or_insert((note.header.clone(), NoteChange::Remove))
                         .1 = NoteChange::Remove;
                 },
-                Change::NoteChanged(key) => {
+                Change::NoteChanged(key, fields) => {
                     let note = note(&key);
                     let file_change = note.span.source.clone().unwrap();
 
                     let inner = changes.entry(file_change).or_insert((FileChange::Noop, IndexMap::new()));
 
-                    inner.1.entry(note.id.clone()).or_insert((note.header.clone(), NoteChange::Modified(None)))
-                        .1 = NoteChange::Modified(None);
+                    let change = match fields.is_empty() {
+                        true => NoteChange::Modified(None),
+                        false => NoteChange::Fields(fields),
+                    };
+
+                    let entry = inner.1.entry(note.id.clone()).or_insert((note.header.clone(), change.clone()));
+                    entry.1 = change;
                 },
                 Change::NoteMoved(key, from, to) => {
                     let note = note(&key);
@@ -249,3 +367,34 @@ impl Notes {
     }
 }
 
+const FALLBACK: &str = " data-fallback=\"";
+
+/// Replace label of links, marked with `data-fallback`, by header of target
+fn fill_fallback_labels(html: &str, headers: &IndexMap<Key, String>) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while let Some(pos) = rest.find(FALLBACK) {
+        let (head, tail) = rest.split_at(pos + FALLBACK.len());
+        out.push_str(head);
+
+        // find target key, end of opening tag and closing tag
+        let (key, open, close) = match (tail.find("\""), tail.find(">"), tail.find("</a>")) {
+            (Some(key), Some(open), Some(close)) if key < open && open < close => (key, open, close),
+            _ => { rest = tail; continue },
+        };
+
+        let target = tail[..key].replace("&quot;", "\"").replace("&lt;", "<").replace("&gt;", ">").replace("&amp;", "&");
+        let label = match headers.get(&target) {
+            Some(header) if !header.is_empty() => header.replace("&", "&amp;").replace("<", "&lt;").replace(">", "&gt;"),
+            _ => tail[..key].to_string(),
+        };
+
+        out.push_str(&tail[..open + 1]);
+        out.push_str(&label);
+        rest = &tail[close..];
+    }
+
+    out.push_str(rest);
+    out
+}
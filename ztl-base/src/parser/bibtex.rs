@@ -1,51 +1,129 @@
 use std::path::PathBuf;
 use line_numbers::LinePositions;
+use indexmap::IndexMap;
+use biblatex::{Bibliography, ChunksExt, DateValue, PermissiveType, RawBibliography, RawEntry, Spanned};
+use hayagriva::{BufWriteFormat, Library};
 
-use crate::{LineColumn, Span, Note, error::ParseReport, error::*};
+use crate::{Outgoing, LineColumn, Span, Note, Reference, error::ParseReport, error::*};
+use crate::config::Config;
+use super::{NoteParser, Citations};
+use super::tokenizer::Tokenizer;
 
-pub(crate) fn analyze(content: &str, source: &PathBuf) -> Result<Vec<Note>> {
-    let mut spans = Vec::new();
+/// Literature notes from BibTeX/BibLaTeX entries, formatted with CSL style
+pub struct Bibtex {
+    config: Config,
+}
 
-    for (i, line) in content.split("\n").enumerate() {
-        if line.trim().starts_with("@") {
-            spans.push((i,0))
-        }
-        if line.trim().starts_with("}") {
-            spans.last_mut().map(|x| x.1 = i);
-        }
+impl Bibtex {
+    pub fn new(config: &Config) -> Self {
+        Bibtex { config: config.clone() }
     }
+}
+
+impl NoteParser for Bibtex {
+    fn extensions(&self) -> &[&str] {
+        &["bib"]
+    }
+
+    fn parse(&self, content: &str, source: &PathBuf, report: &mut ParseReport) -> Result<Vec<Note>> {
+        let (citations, dependencies) = super::literature::citations(&self.config)?;
+
+        analyze(content, source, &citations, &dependencies, report)
+    }
+
+    fn dependencies(&self) -> Vec<PathBuf> {
+        super::literature::style_files(&self.config)
+    }
+}
+
+/// Fields referencing other entries, these are converted to outgoing links
+const LINK_FIELDS: &[&str] = &["crossref", "xdata", "xref", "related"];
 
-    let bib = biblatex::Bibliography::parse(&content)
+pub(crate) fn analyze(content: &str, source: &PathBuf, citations: &Citations, dependencies: &str, report: &mut ParseReport) -> Result<Vec<Note>> {
+    // keep raw entries for spans and links, `xdata` is removed while resolving
+    let parse = || -> std::result::Result<_, biblatex::ParseError> {
+        let raw = RawBibliography::parse(content)?;
+        Ok((raw.clone(), Bibliography::from_raw(raw)?))
+    };
+
+    let (raw, bib) = parse()
         .map_err(|err| {
+            let mut spans = Vec::new();
+            for (i, line) in content.split("\n").enumerate() {
+                if line.trim().starts_with("@") {
+                    spans.push((i,0))
+                }
+                if line.trim().starts_with("}") {
+                    spans.last_mut().map(|x| x.1 = i);
+                }
+            }
+
             // find line of offending bib entry
             let line_positions = LinePositions::from(content);
             let (line1, col1) = line_positions.from_offset(err.span.start);
             let (line2, col2) = line_positions.from_offset(err.span.end);
 
-            let (start, end) = spans.iter().filter(|x| line1.as_usize() <= x.1 && line1.as_usize() >= x.0)
-                .next().unwrap();
-
-            let note = Span {
-                source: Some(source.clone()),
-                start: LineColumn { line: start + 1, column: None },
-                end: LineColumn { line: *end, column: None },
-            };
-
             let problem = Span {
                 source: Some(source.clone()),
-                start: LineColumn { line: line1.as_usize() + 1, column: Some(col1) },
-                end: LineColumn { line: line2.as_usize() + 1, column: Some(col2) },
+                start: LineColumn { line: line1.as_usize() + 1, column: Some(col1 + 1) },
+                end: LineColumn { line: line2.as_usize() + 1, column: Some(col2 + 1) },
             };
 
-            Error::Parse(ParseReport::new(&note, &problem, &format!("{}", err.kind)))
+            // entry may not be found, if the error occurs outside of any entry
+            let note = spans.iter().filter(|x| line1.as_usize() <= x.1 && line1.as_usize() >= x.0)
+                .next()
+                .map(|(start, end)| Span {
+                    source: Some(source.clone()),
+                    start: LineColumn { line: start + 1, column: None },
+                    end: LineColumn { line: *end, column: None },
+                })
+                .unwrap_or(problem.clone());
+
+            Error::Parse(ParseReport::new(&note, &problem, Code::InvalidBibtex, &format!("{}", err.kind)))
         })?;
 
-    bib.into_iter().zip(spans.into_iter()).map(|(bib, span)| {
-        let span = Span {
+    let positions = Tokenizer::new(content);
+    let raw = raw.entries.into_iter()
+        .map(|x| (x.v.key.v.to_string(), x))
+        .collect::<IndexMap<_, _>>();
+
+    let entry_span = |entry: &Spanned<RawEntry>| {
+        // the closing brace is not part of the entry span
+        let end = content[entry.span.end..].find("}")
+            .map(|x| entry.span.end + x)
+            .unwrap_or(entry.span.end);
+
+        Span {
             source: Some(source.clone()),
-            start: LineColumn { line: span.0 + 1, column: None },
-            end: LineColumn { line: span.1, column: None },
-        };
+            start: LineColumn { line: positions.position(entry.span.start).line, column: None },
+            end: LineColumn { line: positions.position(end).line - 1, column: None },
+        }
+    };
+
+    // entries with invalid fields are reported and not formatted, all other
+    // entries are formatted together
+    let mut library = Library::new();
+    for bib in bib.iter() {
+        match hayagriva::Entry::try_from(bib) {
+            Ok(entry) => library.push(&entry),
+            Err(err) => {
+                let problem = Span {
+                    source: Some(source.clone()),
+                    start: positions.position(err.span.start),
+                    end: positions.position(err.span.end.max(err.span.start + 1) - 1),
+                };
+
+                report.append(ParseReport::new(&entry_span(&raw[&bib.key]), &problem, Code::UnformattableEntry,
+                    &format!("entry `{}` cannot be formatted: {}", bib.key, err.kind)));
+            },
+        }
+    }
+
+    let mut html = citations.format(&library, BufWriteFormat::Html, false);
+
+    bib.into_iter().map(|bib| {
+        let entry = &raw[&bib.key];
+        let span = entry_span(entry);
 
         // resource field get precedence to file field, get precedence to 
         // URL field
@@ -59,20 +137,115 @@ pub(crate) fn analyze(content: &str, source: &PathBuf) -> Result<Vec<Note>> {
             .unwrap_or("").to_string();
 
         let kind = Some(bib.entry_type.to_string());
+        let reference = reference(&bib);
+
+        // normalized fields, with inherited fields from `crossref` and `xdata`
+        let metadata = bib.fields.iter()
+            .map(|(k, v)| (k.clone(), v.format_verbatim().split_whitespace().collect::<Vec<_>>().join(" ")))
+            .collect::<IndexMap<_, _>>();
+
+        let normalized = metadata.iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect::<Vec<_>>()
+            .join("\n");
 
         Ok(Note {
             id: bib.key.clone(),
             header, kind, 
             parent: None,
             children: Vec::new(),
-            outgoing: Vec::new(),
+            outgoing: links(content, entry, &positions),
+            external: Vec::new(),
             incoming: Vec::new(),
-            html: String::new(),
+            html: html.shift_remove(&bib.key).map(|x| format!("<div class=\"csl-entry\">{}</div>", x)).unwrap_or_default(),
             span,
             resource,
-            hash: crate::utils::hash(&bib.key),
+            hash: crate::utils::hash(&format!("{}{}{}\n{}", dependencies, bib.entry_type, bib.key, normalized)),
             public: false,
             cards: Vec::new(),
+            metadata: metadata.into_iter().map(|(k, v)| (k, toml::Value::String(v))).collect(),
+            reference: Some(reference),
+            anchors: None,
+            pages: None,
+            pdf: None,
         })
     }).collect()
 }
+
+/// Links to other entries from `crossref`, `xdata`, `xref` and `related` fields
+fn links(content: &str, entry: &Spanned<RawEntry>, positions: &Tokenizer) -> Vec<Outgoing> {
+    let mut outgoing = Vec::new();
+
+    for field in entry.v.fields.iter().filter(|x| LINK_FIELDS.contains(&x.key.v.to_lowercase().as_str())) {
+        let value = &content[field.value.span.clone()];
+
+        let mut offset = field.value.span.start;
+        for part in value.split(",") {
+            let start = offset;
+            offset += part.len() + 1;
+
+            let key = part.trim_matches(|x: char| x.is_whitespace() || x == '{' || x == '}' || x == '"');
+            if key.is_empty() {
+                continue;
+            }
+
+            let start = start + part.find(key).unwrap();
+            outgoing.push(Outgoing {
+                target: key.to_string(),
+                comment: String::new(),
+                label: field.key.v.to_lowercase(),
+                view: None,
+                span: Span {
+                    source: None,
+                    start: positions.position(start),
+                    end: positions.position(start + key.len() - 1),
+                },
+            });
+        }
+    }
+
+    outgoing
+}
+
+/// Entries of BibTeX file converted for formatting, entries which cannot be
+/// converted are returned separately with the reason
+pub(crate) fn library(content: &str) -> std::result::Result<(Library, IndexMap<String, String>), biblatex::ParseError> {
+    let mut library = Library::new();
+    let mut failed = IndexMap::new();
+
+    for bib in Bibliography::parse(content)?.iter() {
+        match hayagriva::Entry::try_from(bib) {
+            Ok(entry) => library.push(&entry),
+            Err(err) => { failed.insert(bib.key.clone(), err.kind.to_string()); },
+        }
+    }
+
+    Ok((library, failed))
+}
+
+/// Extract bibliographic metadata, malformed fields are skipped
+fn reference(bib: &biblatex::Entry) -> Reference {
+    let year = match bib.date() {
+        Ok(PermissiveType::Typed(date)) => match date.value {
+            DateValue::At(x) | DateValue::After(x) | DateValue::Before(x) | DateValue::Between(x, _) => Some(x.year),
+        },
+        // fall back to literal dates, starting with the year
+        Ok(PermissiveType::Chunks(chunks)) => chunks.format_verbatim().get(..4).and_then(|x| x.parse().ok()),
+        Err(_) => None,
+    };
+
+    let keywords = bib.keywords().map(|x| x.format_verbatim()).unwrap_or_default()
+        .split(|x| x == ',' || x == ';')
+        .map(|x| x.trim().to_string())
+        .filter(|x| !x.is_empty())
+        .collect();
+
+    Reference {
+        authors: bib.author().unwrap_or_default().iter().map(|x| x.to_string()).collect(),
+        year,
+        doi: bib.doi().ok(),
+        journal: bib.journal().ok().map(|x| x.format_verbatim()),
+        abstract_: bib.abstract_().ok().map(|x| x.format_verbatim()),
+        keywords,
+    }
+}
@@ -0,0 +1,127 @@
+use std::{fs, path::Path};
+use indexmap::IndexMap;
+use hayagriva::{BibliographyDriver, BibliographyRequest, CitationItem, CitationRequest, Library};
+use hayagriva::archive::{ArchivedStyle, locales};
+use hayagriva::citationberg::{IndependentStyle, Locale, LocaleCode, Style};
+
+use crate::config::Config;
+use crate::notes::Notes;
+use crate::error::*;
+
+pub use hayagriva::BufWriteFormat;
+
+/// Formatter of bibliography entries with a CSL style
+///
+/// Styles are either bundled with hayagriva or read from a CSL file, hence
+/// no network access is required.
+pub struct Citations {
+    style: IndependentStyle,
+    locale: Option<LocaleCode>,
+    locales: Vec<Locale>,
+}
+
+impl Citations {
+    /// Load style by name (e.g. `apa`, `ieee`) or from CSL file relative to root
+    pub fn new(config: &Config, style: &str) -> Result<Self> {
+        let style = match config.csl_file(style) {
+            Some(path) => IndependentStyle::from_xml(&fs::read_to_string(&path)?)
+                .map_err(|_| Error::UnknownStyle(path.display().to_string()))?,
+            None => match ArchivedStyle::by_name(style).map(|x| x.get()) {
+                Some(Style::Independent(style)) => style,
+                _ => return Err(Error::UnknownStyle(style.to_string())),
+            },
+        };
+
+        Ok(Citations {
+            style,
+            locale: config.bibliography.locale.clone().map(LocaleCode),
+            locales: locales(),
+        })
+    }
+
+    /// Format entries of library as references, keyed by entry key
+    ///
+    /// Entries are cited in order of the library, labels (e.g. `[1]` for
+    /// numeric styles) are only included if requested.
+    pub fn format(&self, library: &Library, format: BufWriteFormat, label: bool) -> IndexMap<String, String> {
+        let mut driver = BibliographyDriver::new();
+        for entry in library.iter() {
+            driver.citation(CitationRequest::new(
+                vec![CitationItem::with_entry(entry)], &self.style, self.locale.clone(), &self.locales, None));
+        }
+
+        let rendered = driver.finish(BibliographyRequest::new(&self.style, self.locale.clone(), &self.locales));
+        let mut references = rendered.bibliography
+            .map(|bib| bib.items.into_iter().map(|item| {
+                let mut out = String::new();
+                if let Some(first) = item.first_field.filter(|_| label) {
+                    first.write_buf(&mut out, format).unwrap();
+                    out.push(' ');
+                }
+                item.content.write_buf(&mut out, format).unwrap();
+
+                (item.key, out)
+            }).collect::<IndexMap<_, _>>())
+            .unwrap_or_default();
+
+        // styles without bibliography, fall back to the citation itself
+        if references.is_empty() {
+            for (entry, citation) in library.iter().zip(rendered.citations) {
+                let mut out = String::new();
+                citation.citation.write_buf(&mut out, format).unwrap();
+                references.insert(entry.key().to_string(), out);
+            }
+        }
+
+        // keep order of the library
+        library.iter()
+            .filter_map(|x| references.shift_remove(x.key()).map(|html| (x.key().to_string(), html)))
+            .collect()
+    }
+}
+
+/// Collect bibliography entries from their sources, in order of keys
+///
+/// Sources are BibLaTeX, CSL-JSON, RIS files or a Zotero database, depending
+/// on extension. They are read relative to the repository root.
+pub fn load_library(notes: &Notes, root: &Path, keys: &[String]) -> Result<Library> {
+    let mut sources = IndexMap::new();
+    let mut library = Library::new();
+
+    for key in keys {
+        let source = notes.notes.get(key)
+            .filter(|x| x.reference.is_some())
+            .and_then(|x| x.span.source.clone())
+            .ok_or_else(|| Error::UnknownEntry(key.clone()))?;
+
+        // BibTeX entries are converted one by one, failures are only
+        // reported for requested keys, malformed records fail the source
+        if !sources.contains_key(&source) {
+            let path = root.join(&source);
+            let mut problems = ParseReport::in_root(root);
+            let entries = match source.extension().and_then(|x| x.to_str()) {
+                Some("json") => (super::literature::library(&super::csl_json::records(&fs::read_to_string(&path)?, &source, &mut problems)?), IndexMap::new()),
+                Some("ris") => (super::literature::library(&super::ris::records(&fs::read_to_string(&path)?, &source, &mut problems)?), IndexMap::new()),
+                #[cfg(feature = "zotero")]
+                Some("sqlite") => (super::literature::library(&super::zotero::records(&path, &source, None)?), IndexMap::new()),
+                _ => super::bibtex::library(&fs::read_to_string(&path)?)
+                    .map_err(|err| Error::InvalidBibliography(source.clone(), err.to_string()))?,
+            };
+            problems.as_err()?;
+
+            sources.insert(source.clone(), entries);
+        }
+
+        let (entries, failed) = &sources[&source];
+        if let Some(reason) = failed.get(key) {
+            return Err(Error::UnformattableEntry(key.clone(), reason.clone()));
+        }
+
+        let entry = entries.get(key)
+            .ok_or_else(|| Error::UnknownEntry(key.clone()))?;
+
+        library.push(entry);
+    }
+
+    Ok(library)
+}
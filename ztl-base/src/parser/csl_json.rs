@@ -0,0 +1,218 @@
+use std::path::PathBuf;
+use serde_json::Value;
+
+use crate::{LineColumn, Span, Note, error::ParseReport, error::*};
+use crate::config::Config;
+use super::NoteParser;
+use super::literature::{self, Record};
+use super::tokenizer::Tokenizer;
+
+/// Literature notes from CSL-JSON items, formatted with CSL style
+///
+/// Only files with `.csl.json` suffix are considered bibliographies, other
+/// JSON files in the repository (e.g. `package.json`) are skipped.
+pub struct CslJson {
+    config: Config,
+}
+
+impl CslJson {
+    pub fn new(config: &Config) -> Self {
+        CslJson { config: config.clone() }
+    }
+}
+
+impl NoteParser for CslJson {
+    fn extensions(&self) -> &[&str] {
+        &["csl.json"]
+    }
+
+    fn parse(&self, content: &str, source: &PathBuf, report: &mut ParseReport) -> Result<Vec<Note>> {
+        let (citations, dependencies) = literature::citations(&self.config)?;
+        let records = records(content, source, report)?;
+
+        Ok(literature::notes(records, &citations, &dependencies))
+    }
+
+    fn dependencies(&self) -> Vec<PathBuf> {
+        literature::style_files(&self.config)
+    }
+}
+
+/// Convert items of CSL-JSON array to records
+///
+/// Syntax errors fail the whole file. Items which are no objects and items
+/// without `id` are added to the report, all other items are returned.
+pub(crate) fn records(content: &str, source: &PathBuf, report: &mut ParseReport) -> Result<Vec<Record>> {
+    let positions = Tokenizer::new(content);
+    let ranges = item_ranges(content);
+
+    // span of whole item, with 1-based start and 0-based end line
+    let item_span = |(start, end): (usize, usize)| Span {
+        source: Some(source.clone()),
+        start: LineColumn { line: positions.position(start).line, column: None },
+        end: LineColumn { line: positions.position(end).line - 1, column: None },
+    };
+
+    let items = serde_json::from_str::<Vec<Value>>(content)
+        .map_err(|err| {
+            let position = LineColumn { line: err.line().max(1), column: Some(err.column().max(1)) };
+            let problem = Span { source: Some(source.clone()), start: position.clone(), end: position };
+
+            // item may not be found, if the error occurs outside of any item
+            let note = ranges.iter()
+                .map(|x| item_span(*x))
+                .filter(|x| err.line() >= x.start.line && err.line() <= x.end.line + 1)
+                .next()
+                .unwrap_or(problem.clone());
+
+            // position is already part of the span
+            let reason = err.to_string();
+            let reason = reason.split(" at line ").next().unwrap_or("");
+
+            Error::Parse(ParseReport::new(&note, &problem, Code::InvalidCslJson, reason))
+        })?;
+
+    let mut records = Vec::new();
+    for (item, range) in items.into_iter().zip(ranges) {
+        let span = item_span(range);
+        let problem = Span {
+            source: Some(source.clone()),
+            start: positions.position(range.0),
+            end: positions.position(range.0),
+        };
+
+        let item = match item {
+            Value::Object(x) => x,
+            _ => { report.append(ParseReport::new(&span, &problem, Code::InvalidCslJson, "CSL item is not an object")); continue },
+        };
+
+        let key = match item.get("id").map(text) {
+            Some(x) if !x.is_empty() => x,
+            _ => { report.append(ParseReport::new(&span, &problem, Code::InvalidCslJson, "CSL item without `id`")); continue },
+        };
+
+        let mut record = Record {
+            key,
+            kind: item.get("type").map(text).unwrap_or("document".to_string()),
+            title: item.get("title").map(text).unwrap_or_default(),
+            authors: item.get("author").and_then(|x| x.as_array()).map(|x| x.iter().map(name).collect()).unwrap_or_default(),
+            date: item.get("issued").and_then(date),
+            doi: item.get("DOI").map(text),
+            container: item.get("container-title").map(text),
+            abstract_: item.get("abstract").map(text),
+            keywords: item.get("keyword").map(text).unwrap_or_default()
+                .split(|x| x == ',' || x == ';')
+                .map(|x| x.trim().to_string())
+                .filter(|x| !x.is_empty())
+                .collect(),
+            url: item.get("URL").map(text),
+            file: item.get("file").map(text),
+            resource: item.get("resource").map(text),
+            span,
+            ..Default::default()
+        };
+
+        for (key, value) in item.iter().filter(|(k, _)| *k != "id" && *k != "type") {
+            let value = match value {
+                Value::Array(x) if x.iter().all(|x| x.is_object()) =>
+                    x.iter().map(|x| match name(x) {
+                        (family, given) if given.is_empty() => family,
+                        (family, given) => format!("{}, {}", family, given),
+                    }).collect::<Vec<_>>().join("; "),
+                Value::Object(_) => date(value).unwrap_or_else(|| text(value)),
+                _ => text(value),
+            };
+
+            record.push_field(key, &value);
+        }
+
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+/// Byte ranges of the items in the top-level array, from first to last
+/// character
+fn item_ranges(content: &str) -> Vec<(usize, usize)> {
+    let mut ranges = Vec::new();
+    let (mut depth, mut item, mut last) = (0, None, 0);
+    let (mut string, mut escaped) = (false, false);
+
+    for (i, c) in content.char_indices() {
+        if string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => string = false,
+                _ => {},
+            }
+            last = i;
+            continue;
+        }
+
+        if c.is_whitespace() {
+            continue;
+        }
+
+        if depth == 1 && item.is_none() && c != ',' && c != ']' {
+            item = Some(i);
+        }
+
+        match c {
+            '"' => string = true,
+            '[' | '{' => depth += 1,
+            ']' | '}' => depth -= 1,
+            _ => {},
+        }
+
+        // items end at separating comma or the end of the array
+        if (depth == 1 && c == ',') || depth == 0 {
+            if let Some(start) = item.take() {
+                ranges.push((start, last));
+            }
+        } else {
+            last = i;
+        }
+    }
+
+    ranges
+}
+
+/// Value as text, numbers are converted and arrays joined
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(x) => x.split_whitespace().collect::<Vec<_>>().join(" "),
+        Value::Array(x) => x.iter().map(text).collect::<Vec<_>>().join("; "),
+        Value::Null => String::new(),
+        x => x.to_string(),
+    }
+}
+
+/// Family and given name of CSL name variable, literal names have no given
+/// name
+fn name(value: &Value) -> (String, String) {
+    match value.get("literal") {
+        Some(x) => (text(x), String::new()),
+        None => (
+            value.get("family").map(text).unwrap_or_default(),
+            value.get("given").map(text).unwrap_or_default(),
+        ),
+    }
+}
+
+/// Date variable as `year[-month[-day]]`, falls back to raw or literal dates
+fn date(value: &Value) -> Option<String> {
+    let parts = value.get("date-parts")
+        .and_then(|x| x.get(0))
+        .and_then(|x| x.as_array())
+        .map(|x| x.iter().filter_map(|x| text(x).parse::<i32>().ok()).collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    match parts.as_slice() {
+        [] => value.get("raw").or(value.get("literal")).map(text),
+        [year] => Some(format!("{:04}", year)),
+        [year, month] => Some(format!("{:04}-{:02}", year, month)),
+        [year, month, day, ..] => Some(format!("{:04}-{:02}-{:02}", year, month, day)),
+    }
+}
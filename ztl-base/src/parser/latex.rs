@@ -3,81 +3,137 @@ use std::process::Command;
 use std::io::Write;
 use std::fs;
 use once_cell::sync::Lazy;
+use indexmap::IndexMap;
 use scraper::{Html, Selector, Element, HtmlTreeSink};
 use regex::Regex;
 use markup5ever::interface::tree_builder::TreeSink;
 
 use crate::{Outgoing, LineColumn, Span, Note, Card, error::*};
-use crate::config::Config;
+use crate::config::{Config, LatexBackend};
+use super::NoteParser;
+use super::tokenizer::{Tokenizer, is_verbatim, key_values};
 
 #[derive(Default, Debug)]
 struct LatexNote {
     env_kind: String,
     label: String,
     title: String,
-    content: Vec<String>,
+    /// first and last line of the note
     start: usize,
     end: usize,
+    /// byte offsets of `\begin` and after `\end{..}`
+    begin: usize,
+    finish: usize,
     parent: Option<String>,
+    outgoing: Vec<Outgoing>,
+    cards: Vec<Card>,
+    /// labels defined inside the note
+    anchors: Vec<String>,
 }
 
-pub fn latex_to_html(config: &Config, note: &Note) -> Result<String> {
-    let preamble = fs::read_to_string(&config.latex_preamble()).unwrap();
-    let preamble_len = preamble.lines().count();
+/// LaTeX notes from environments with `label` and `name` argument
+pub struct Latex {
+    config: Config,
+}
 
-    let tmp_dir = tempfile::TempDir::new().unwrap();
+impl Latex {
+    pub fn new(config: &Config) -> Self {
+        Latex { config: config.clone() }
+    }
+}
 
-    let out_file = tmp_dir.path().join("main.tex");
-    let mut f = std::fs::File::create(out_file.to_str().unwrap()).unwrap();
+impl NoteParser for Latex {
+    fn extensions(&self) -> &[&str] {
+        &["tex"]
+    }
 
-    f.write(preamble.as_bytes()).unwrap();
-    f.write(b"\\begin{document}").unwrap();
-    f.write(note.html.as_bytes()).unwrap();
-    f.write(b"\\end{document}").unwrap();
+    fn parse(&self, content: &str, source: &PathBuf, report: &mut ParseReport) -> Result<Vec<Note>> {
+        // notes are rendered again, when switching backend or command
+        let dependencies = format!("{}{:?}{}", super::dependency_hash(&self.dependencies()),
+            self.config.latex.backend, self.config.latex_build());
 
-    let out_dir = tmp_dir.path().to_str().unwrap();
-    let cfg_dir = std::env::current_dir().unwrap().join(".ztl").join("thmtav.cfg");
-    let out = Command::new("make4ht")
-        .args(["-a", "debug", "-c", cfg_dir.to_str().unwrap(), "-m", "draft", out_file.to_str().unwrap()])
-        .current_dir(out_dir)
-        .output().unwrap();
+        analyze(&self.config, content, source, &dependencies, report)
+    }
 
-    if !out.status.success() {
-        let re = Regex::new(r"(?m)^.*l\.(\d+)\s.*$").unwrap();
+    fn render_html(&self, note: &Note) -> Option<Result<String>> {
+        Some(latex_to_html(&self.config, note))
+    }
 
-        let note_span = note.span.clone();
-        let stdout = String::from_utf8(out.stdout).unwrap();
-        let mut line = None;
-        for cap in re.captures_iter(&stdout) {
-            let full_match = cap.get(0).unwrap().as_str();
-            if full_match.contains("--- TeX4ht warning") {
-                continue;
-            }
+    fn dependencies(&self) -> Vec<PathBuf> {
+        std::iter::once(self.config.latex_preamble())
+            .chain(self.config.latex_cfg())
+            .collect()
+    }
+}
 
-            line = Some(cap.get(1).unwrap().as_str().parse::<usize>().unwrap());
-        }
+pub fn latex_to_html(config: &Config, note: &Note) -> Result<String> {
+    let preamble = fs::read_to_string(&config.latex_preamble())?;
+    let preamble_len = preamble.lines().count();
+
+    // rendered HTML is cached by content, e.g. to switch between branches,
+    // and backend, as the post-processing depends on it
+    let cfg_content = match config.latex_cfg() {
+        Some(path) => fs::read_to_string(&path)?,
+        None => String::new(),
+    };
+    let key = crate::utils::hash(&format!("{:?}{}{}{}{}", config.latex.backend, preamble, cfg_content, config.latex_build(), note.html));
+    let cache_file = config.ztl_root().join("cache").join(key).with_extension("html");
+
+    if let Ok(html) = fs::read_to_string(&cache_file) {
+        return Ok(html);
+    }
+
+    let tmp_dir = tempfile::TempDir::new()?;
+
+    let out_file = tmp_dir.path().join("main.tex");
+    let html_file = tmp_dir.path().join("main.html");
+    let mut f = std::fs::File::create(&out_file)?;
+
+    f.write(preamble.as_bytes())?;
+    f.write(b"\\begin{document}")?;
+    f.write(note.html.as_bytes())?;
+    f.write(b"\\end{document}")?;
+
+    let cfg = config.latex_cfg().unwrap_or_default();
+    let cmd = super::command(&config.latex_build(), &[
+        ("file", &out_file),
+        ("output", &html_file),
+        ("dir", tmp_dir.path()),
+        ("root", &config.root),
+        ("cfg", &cfg),
+    ]);
+
+    let out = Command::new("sh")
+        .arg("-c")
+        .arg(&cmd)
+        .current_dir(tmp_dir.path())
+        .output()?;
+
+    let backend = config.latex.backend;
+    if !out.status.success() {
+        let note_span = note.span.clone();
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        let (line, reason) = error_position(backend, &stdout, &stderr);
 
         let problem = if let Some(mut line) = line {
             if line <= preamble_len {
                 return Err(Error::Parse(ParseReport::new(
-                    &Span {
-                        source: Some(config.latex_preamble()),
-                        start: LineColumn { line: 1, column: Some(1) },
-                        end: LineColumn { line: preamble_len, column: None },
-                    }, 
+                    &note_span,
                     &Span {
                         source: Some(config.latex_preamble()),
                         start: LineColumn { line: line, column: Some(1) },
                         end: LineColumn { line: line, column: None },
                     },
-                    "preamble error"
+                    Code::RenderFailed,
+                    &format!("preamble error: {}", reason)
                 )));
             }
 
             line = line - preamble_len - 1;
 
             Span {
-                source: None,
+                source: note_span.source.clone(),
                 start: LineColumn { line: note_span.start.line + line, column: Some(1) },
                 end: LineColumn { line: note_span.start.line + line, column: None }
             }
@@ -85,109 +141,299 @@ pub fn latex_to_html(config: &Config, note: &Note) -> Result<String> {
             note_span.clone()
         };
 
-        //io::stdout().write_all(&out.stdout).unwrap();
-        //io::stderr().write_all(&out.stderr).unwrap();
-
-        Err(Error::Parse(ParseReport::new(&note_span, &problem, "latex problem")))
+        Err(Error::Parse(ParseReport::new(&note_span, &problem, Code::RenderFailed, &reason)))
     } else {
-        let cont = std::fs::read_to_string(tmp_dir.path().join("main.html")).unwrap();
+        let cont = std::fs::read_to_string(&html_file)?;
 
-        let mut document = Html::parse_document(&cont);
-        let modified = HtmlTreeSink::new(document.clone());
+        let html = match backend {
+            LatexBackend::Make4ht => postprocess_make4ht(&cont),
+            LatexBackend::Pandoc => postprocess_pandoc(&cont),
+            LatexBackend::Latexml => postprocess_latexml(&cont),
+        };
 
-        // remove all comments from HTML
-        let rm = document.root_element().descendants().filter(|x| x.value().is_comment()).map(|x| x.id()).collect::<Vec<_>>();
+        fs::create_dir_all(cache_file.parent().unwrap())?;
+        fs::write(&cache_file, &html)?;
 
-        for id in rm {
-            modified.remove_from_parent(&id);
-        }
+        Ok(html)
+    }
+}
 
-        // remove empty links which are used as anchors
-        let rm = document.select(&Selector::parse("a").unwrap()).filter(|x| x.attr("href").is_none()).map(|x| x.id()).collect::<Vec<_>>();
+/// Find line and reason of first error in the output of the backend
+fn error_position(backend: LatexBackend, stdout: &str, stderr: &str) -> (Option<usize>, String) {
+    static RE_TEX_LINE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?m)^.*l\.(\d+)\s.*$").unwrap());
+    static RE_TEX_ERR: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?m)^! (.*)$").unwrap());
+    static RE_PANDOC: Lazy<Regex> = Lazy::new(|| Regex::new(r"line (\d+), column \d+\)?:?\s*(.*)").unwrap());
+    static RE_LATEXML: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?m)^(?:Error|Fatal):[^ ]* (.*?)(?:\n.*)*?\s+at [^;\n]*; line (\d+)").unwrap());
+
+    match backend {
+        LatexBackend::Make4ht => {
+            let line = RE_TEX_LINE.captures_iter(stdout)
+                .filter(|x| !x[0].contains("--- TeX4ht warning"))
+                .last()
+                .map(|x| x[1].parse::<usize>().unwrap());
+
+            let reason = RE_TEX_ERR.captures(stdout)
+                .map(|x| x[1].trim().to_string())
+                .unwrap_or("latex problem".to_string());
+
+            (line, reason)
+        },
+        LatexBackend::Pandoc => match RE_PANDOC.captures(stderr) {
+            Some(x) if !x[2].trim().is_empty() => (x[1].parse().ok(), x[2].trim().to_string()),
+            Some(x) => (x[1].parse().ok(), stderr.lines().last().unwrap_or("pandoc problem").trim().to_string()),
+            None => (None, stderr.lines().next().unwrap_or("pandoc problem").trim().to_string()),
+        },
+        LatexBackend::Latexml => match RE_LATEXML.captures(stderr) {
+            Some(x) => (x[2].parse().ok(), x[1].trim().to_string()),
+            None => (None, "latexml problem".to_string()),
+        },
+    }
+}
 
-        for id in rm {
-            modified.remove_from_parent(&id);
-        }
+/// Clean HTML from make4ht, removes anchors, header and nested rows in overlines
+fn postprocess_make4ht(cont: &str) -> String {
+    let mut document = Html::parse_document(cont);
+    let modified = HtmlTreeSink::new(document.clone());
 
-        // remove middle mrow for overline
-        let rm = document.select(&Selector::parse("mover mrow mrow").unwrap())
-            .map(|x| x.parent_element().unwrap())
-            .map(|x| (x.id(), x.parent_element().unwrap().id())).collect::<Vec<_>>();
+    // remove all comments from HTML
+    let rm = document.root_element().descendants().filter(|x| x.value().is_comment()).map(|x| x.id()).collect::<Vec<_>>();
 
-        for (a,b) in rm {
-            modified.reparent_children(&a,&b);
-            modified.remove_from_parent(&a);
-        }
+    for id in rm {
+        modified.remove_from_parent(&id);
+    }
 
-        // remove header
-        let rm = document.select(&Selector::parse(".head").unwrap()).map(|x| x.id()).collect::<Vec<_>>();
+    // remove empty links which are used as anchors
+    let rm = document.select(&Selector::parse("a").unwrap()).filter(|x| x.attr("href").is_none()).map(|x| x.id()).collect::<Vec<_>>();
 
-        for id in rm {
-            modified.remove_from_parent(&id);
-        }
+    for id in rm {
+        modified.remove_from_parent(&id);
+    }
+
+    // remove middle mrow for overline
+    let rm = document.select(&Selector::parse("mover mrow mrow").unwrap())
+        .map(|x| x.parent_element().unwrap())
+        .map(|x| (x.id(), x.parent_element().unwrap().id())).collect::<Vec<_>>();
 
-        // finish modifications and extract body
-        document = modified.finish();
-        let body = document.select(&Selector::parse("body div").unwrap()).next().unwrap();
-        Ok(body.html())
+    for (a,b) in rm {
+        modified.reparent_children(&a,&b);
+        modified.remove_from_parent(&a);
     }
+
+    // remove header
+    let rm = document.select(&Selector::parse(".head").unwrap()).map(|x| x.id()).collect::<Vec<_>>();
+
+    for id in rm {
+        modified.remove_from_parent(&id);
+    }
+
+    // finish modifications and extract body
+    document = modified.finish();
+    document.select(&Selector::parse("body div").unwrap()).next()
+        .map(|x| x.html())
+        .unwrap_or_default()
 }
 
-pub(crate) fn analyze(_config: &Config, content: &str, source: &PathBuf) -> Result<Vec<Note>> {
-    let mut levels: Vec<LatexNote> = Vec::new();
+/// Clean HTML from pandoc, the output is a fragment without title block
+fn postprocess_pandoc(cont: &str) -> String {
+    let mut document = Html::parse_document(cont);
+    let modified = HtmlTreeSink::new(document.clone());
+
+    // remove title block of standalone documents
+    let rm = document.select(&Selector::parse("#title-block-header").unwrap()).map(|x| x.id()).collect::<Vec<_>>();
+
+    for id in rm {
+        modified.remove_from_parent(&id);
+    }
+
+    document = modified.finish();
+    let body = document.select(&Selector::parse("body").unwrap()).next()
+        .map(|x| x.inner_html())
+        .unwrap_or_default();
+
+    format!("<div>{}</div>", body.trim())
+}
+
+/// Clean HTML from LaTeXML, removes page navigation, footer and logo
+fn postprocess_latexml(cont: &str) -> String {
+    let mut document = Html::parse_document(cont);
+    let modified = HtmlTreeSink::new(document.clone());
+
+    let rm = document.select(&Selector::parse(".ltx_page_navbar, .ltx_page_header, .ltx_page_footer, .ltx_page_logo").unwrap())
+        .map(|x| x.id()).collect::<Vec<_>>();
+
+    for id in rm {
+        modified.remove_from_parent(&id);
+    }
+
+    document = modified.finish();
+    document.select(&Selector::parse(".ltx_page_content, body").unwrap()).next()
+        .map(|x| format!("<div>{}</div>", x.inner_html().trim()))
+        .unwrap_or_default()
+}
+
+pub(crate) fn analyze(_config: &Config, content: &str, source: &PathBuf, dependencies: &str, report: &mut ParseReport) -> Result<Vec<Note>> {
+    let mut tokens = Tokenizer::new(content);
+    let mut errors = ParseReport::empty();
+
+    // stack of open environments, with note if it is labelled
+    let mut levels: Vec<(String, Option<LatexNote>)> = Vec::new();
     let mut notes = Vec::new();
 
-    static RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\\r\{(.*?)\}\{(.*?)\}").unwrap());
-    static RE_CLOZE: Lazy<Regex> = Lazy::new(|| Regex::new(r#"\\cloze(?:\[(?P<title>.*?)\])?\{(?P<target_id>.*?)\}\{(?P<content>.*?)\}"#).unwrap());
-    static RE_ASSUMP: Lazy<Regex> = Lazy::new(|| Regex::new(r#"\\requires\{(?P<requirement>.*?)\}\{(?P<expression>.*?)\}"#).unwrap());
-
-    for (i, line) in content.lines().map(|x| x.trim()).enumerate() {
-        if line.starts_with("\\begin{") && line.contains("label") && line.contains("name") {
-            let mut iter = line.chars().skip(7);
-            let env_kind: String = iter.by_ref().take_while(|x| *x != '}').collect();
-
-            let inner = iter.by_ref().skip(1)
-                .take_while(|x| *x != ']').collect::<String>();
-
-            let inner = inner.split(",")
-                .map(|x| x.splitn(2, "=").map(str::trim).collect::<Vec<_>>())
-                .filter(|x| x.len() == 2)
-                .collect::<Vec<_>>();
-
-            let mut note = LatexNote::default();
-            for i in inner {
-                match i[0] {
-                    "label" => note.label = i[1].to_string(),
-                    "name" => note.title = i[1].to_string(),
-                    _ => {},
+    let span = |tokens: &Tokenizer, start: usize, end: usize| Span {
+        source: None,
+        start: tokens.position(start),
+        // end column is inclusive
+        end: tokens.position(end.saturating_sub(1)),
+    };
+
+    while let Some((name, start)) = tokens.next_command() {
+        match name {
+            "begin" => {
+                let env = match tokens.argument('{', '}') {
+                    Some((a, b)) => content[a..b].trim().to_string(),
+                    None => continue,
+                };
+
+                if is_verbatim(&env) {
+                    tokens.skip_verbatim(&env);
+                    continue;
                 }
-            }
-            note.env_kind = env_kind;
-            note.start = i + 1;
 
-            if let Some(level) = levels.last() {
-                note.parent = Some(level.label.clone());
-            }
+                let mut note = LatexNote::default();
+                if let Some((a, b)) = tokens.argument('[', ']') {
+                    for (k, v) in key_values(&content[a..b]) {
+                        match k.as_str() {
+                            "label" => note.label = v,
+                            "name" => note.title = v,
+                            _ => {},
+                        }
+                    }
+                }
 
-            levels.push(note);
-        }
+                if note.label.is_empty() {
+                    levels.push((env, None));
+                    continue;
+                }
 
-        if let Some(mut note) = levels.pop() {
-            if line.contains(&format!("\\end{{{}}}", &note.env_kind)) {
-                note.content.push(line.to_string());
-                note.end = i;
-                notes.push(note);
-            } else {
-                note.content.push(line.to_string());
-                levels.push(note);
-            }
+                note.env_kind = env.clone();
+                note.start = tokens.position(start).line;
+                note.begin = start;
+                note.parent = levels.iter().rev()
+                    .filter_map(|x| x.1.as_ref().map(|x| x.label.clone()))
+                    .next();
+
+                levels.push((env, Some(note)));
+            },
+            "end" => {
+                let env = match tokens.argument('{', '}') {
+                    Some((a, b)) => content[a..b].trim().to_string(),
+                    None => continue,
+                };
+
+                // ignore closing commands without opening, e.g. in definitions
+                let pos = match levels.iter().rposition(|x| x.0 == env) {
+                    Some(x) => x,
+                    None => continue,
+                };
+
+                for (inner, note) in levels.split_off(pos + 1) {
+                    if let Some(note) = note {
+                        errors.append(unclosed(source, &tokens, &note, &inner));
+                    }
+                }
+
+                if let Some((_, Some(mut note))) = levels.pop() {
+                    note.end = tokens.position(start).line - 1;
+                    note.finish = tokens.offset();
+                    notes.push(note);
+                }
+            },
+            "r" => {
+                let (target, label) = match (tokens.argument('{', '}'), tokens.argument('{', '}')) {
+                    (Some(target), Some(label)) => (target, label),
+                    _ => continue,
+                };
+
+                let (target, view) = super::split_view(content[target.0..target.1].trim());
+                let link = span(&tokens, start, tokens.offset());
+
+                // invalid modifiers are reported for the innermost note, the
+                // link is kept without view
+                let view = view.unwrap_or_else(|problem| {
+                    if let Some(note) = levels.iter().rev().find_map(|x| x.1.as_ref()) {
+                        let note = Span {
+                            source: Some(source.clone()),
+                            start: LineColumn { line: note.start, column: None },
+                            end: LineColumn { line: link.end.line - 1, column: None },
+                        };
+                        report.append(ParseReport::new(&note, &link, Code::InvalidView, &problem));
+                    }
+                    None
+                });
+
+                let outgoing = Outgoing {
+                    target,
+                    comment: String::new(),
+                    label: content[label.0..label.1].split_whitespace().collect::<Vec<_>>().join(" "),
+                    view,
+                    span: link,
+                };
+
+                // links belong to the innermost note only
+                if let Some(note) = levels.iter_mut().rev().find_map(|x| x.1.as_mut()) {
+                    note.outgoing.push(outgoing);
+                }
+            },
+            "label" => {
+                let label = match tokens.argument('{', '}') {
+                    Some((a, b)) => content[a..b].trim().to_string(),
+                    None => continue,
+                };
+
+                // labels can be referenced from all enclosing notes
+                for note in levels.iter_mut().filter_map(|x| x.1.as_mut()) {
+                    note.anchors.push(label.clone());
+                }
+            },
+            "cloze" => {
+                let description = tokens.argument('[', ']')
+                    .map(|(a, b)| content[a..b].to_string())
+                    .unwrap_or_default();
+
+                let target = match (tokens.argument('{', '}'), tokens.argument('{', '}')) {
+                    (Some(target), Some(_content)) => content[target.0..target.1].to_string(),
+                    _ => continue,
+                };
+
+                if let Some(note) = levels.iter_mut().rev().find_map(|x| x.1.as_mut()) {
+                    note.cards.push(Card::Cloze { description, target });
+                }
+            },
+            "requires" => {
+                let requirement = match (tokens.argument('{', '}'), tokens.argument('{', '}')) {
+                    (Some(requirement), Some(_expression)) => content[requirement.0..requirement.1].to_string(),
+                    _ => continue,
+                };
+
+                if let Some(note) = levels.iter_mut().rev().find_map(|x| x.1.as_mut()) {
+                    note.cards.push(Card::Assumption { target: requirement });
+                }
+            },
+            "verb" => tokens.skip_verb(),
+            _ => {},
         }
     }
 
-    if levels.len() > 0 {
-        dbg!(&levels);
-        panic!("Inbalance in environments found for {}", source.display());
+    // report all environments, which were never closed
+    for (env, note) in &levels {
+        if let Some(note) = note {
+            errors.append(unclosed(source, &tokens, note, env));
+        }
     }
+    errors.as_err()?;
+
+    // sort notes by their appearance in the document
+    notes.sort_by_key(|x| x.begin);
 
     notes.into_iter().map(|note| {
         let span = Span {
@@ -202,69 +448,9 @@ pub(crate) fn analyze(_config: &Config, content: &str, source: &PathBuf) -> Resu
             }
         };
 
-        let outgoing = note.content.iter().enumerate().map(|(l,x)|
-            RE.captures_iter(&x).into_iter().map(|x| {
-                let span = Span {
-                    source: None,
-                    start: LineColumn {
-                        line: span.start.line + l,
-                        column: Some(x.get(0).unwrap().start() + 2),
-                    },
-                    end: LineColumn {
-                        line: span.start.line + l,
-                        column: Some(x.get(0).unwrap().end() + 1),
-                    },
-                };
-
-                let parts = x.get(1).unwrap().as_str().split("#").collect::<Vec<_>>();
-                let keywords = parts[1..].into_iter()
-                    .map(|x| x.splitn(2, "=").collect::<Vec<_>>())
-                    .map(|x| {
-                        if x.len() == 1 {
-                            return ("anchor".to_string(), x[0].to_string());
-                        } else {
-                            return (x[0].to_string(), x[1].to_string());
-                        }
-                    })
-                    .collect();
-
-                Outgoing {
-                    target: parts[0].to_string(),
-                    comment: String::new(),
-                    label: x.get(2).unwrap().as_str().to_string(),
-                    view: keywords,
-                    span
-                }
-            }).collect::<Vec<_>>()
-        ).flatten().collect();
-
-        let content = note.content.join("\n");
-
-        let clozes = RE_CLOZE.captures_iter(&content)
-            .map(|caps| {
-                let description = caps.name("title").map_or("", |m| m.as_str()).to_string();
-                let target_id = caps.name("target_id").unwrap().as_str();
-                let _content = caps.name("content").unwrap().as_str();
-
-                Card::Cloze {
-                    description,
-                    target: target_id.to_string(),
-                }
-            });
-
-        let assumptions = RE_ASSUMP.captures_iter(&content)
-            .map(|caps| {
-                let requirement = caps.name("requirement").unwrap().as_str();
-                //let expression = caps.name("expression").unwrap().as_str();
-
-                Card::Assumption {
-                    target: requirement.to_string(),
-                }
-            });
-
-        let mut cards = clozes.chain(assumptions)
-            .collect::<Vec<_>>();
+        let content = &content[note.begin..note.finish];
 
+        let mut cards = note.cards;
         cards.sort_by_key(|x| match x {
                 Card::Cloze { target, ..} => target.clone(),
                 Card::Assumption { target, .. } => target.clone(), });
@@ -279,14 +465,36 @@ pub(crate) fn analyze(_config: &Config, content: &str, source: &PathBuf) -> Resu
             kind: Some(note.env_kind),
             parent: note.parent,
             children: Vec::new(),
-            outgoing,
+            outgoing: note.outgoing,
+            external: Vec::new(),
             incoming: Vec::new(),
             span,
             resource: None,
-            hash: crate::utils::hash(&content),
-            html: content,
+            // changes of the preamble affect all notes
+            hash: crate::utils::hash(&format!("{}{}", dependencies, content)),
+            html: content.to_string(),
             public: false,
             cards,
+            metadata: IndexMap::new(),
+            reference: None,
+            anchors: Some(note.anchors),
+            pages: None,
+            pdf: None,
         })
     }).collect()
 }
+
+fn unclosed(source: &PathBuf, tokens: &Tokenizer, note: &LatexNote, env: &str) -> ParseReport {
+    let note_span = Span {
+        source: Some(source.clone()),
+        start: LineColumn { line: note.start, column: None },
+        end: LineColumn { line: tokens.src().lines().count().saturating_sub(1), column: None },
+    };
+    let problem = Span {
+        source: Some(source.clone()),
+        start: tokens.position(note.begin),
+        end: LineColumn { line: note.start, column: None },
+    };
+
+    ParseReport::new(&note_span, &problem, Code::UnclosedEnvironment, &format!("environment `{}` is never closed", env))
+}
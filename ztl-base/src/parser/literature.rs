@@ -0,0 +1,190 @@
+//! Literature notes from bibliography formats other than BibLaTeX
+//!
+//! CSL-JSON items and RIS records are first converted to [`Record`]s, which
+//! are then turned into notes the same way as BibTeX entries.
+
+use indexmap::IndexMap;
+use hayagriva::{BufWriteFormat, Library};
+use serde_yaml::{Mapping, Value};
+
+use crate::{Note, Reference, Span, config::Config, error::Result};
+use super::Citations;
+
+/// Configured citation style, with a fingerprint of its settings
+///
+/// The fingerprint is part of the note hash, so that all literature notes
+/// are formatted again when the style changes.
+pub(crate) fn citations(config: &Config) -> Result<(Citations, String)> {
+    let citations = Citations::new(config, &config.bibliography.style)?;
+    let dependencies = format!("{}{:?}{}",
+        config.bibliography.style, config.bibliography.locale, super::dependency_hash(&style_files(config)));
+
+    Ok((citations, dependencies))
+}
+
+/// CSL file of the configured style, if not bundled with hayagriva
+pub(crate) fn style_files(config: &Config) -> Vec<std::path::PathBuf> {
+    config.csl_file(&config.bibliography.style).into_iter().collect()
+}
+
+/// Bibliography entry, independent of its source format
+#[derive(Default)]
+pub(crate) struct Record {
+    pub key: String,
+    /// CSL item type (e.g. `article-journal`, `book`)
+    pub kind: String,
+    pub title: String,
+    /// Authors as family and given name
+    pub authors: Vec<(String, String)>,
+    /// Issue date as `year[-month[-day]]`
+    pub date: Option<String>,
+    pub doi: Option<String>,
+    /// Title of the journal, book or proceedings containing this entry
+    pub container: Option<String>,
+    pub abstract_: Option<String>,
+    pub keywords: Vec<String>,
+    pub url: Option<String>,
+    pub file: Option<String>,
+    pub resource: Option<String>,
+    /// All fields of the record, with collapsed whitespace
+    pub fields: IndexMap<String, String>,
+    pub span: Span,
+}
+
+impl Record {
+    /// Add field to metadata, repeated fields are separated by `; `
+    pub(crate) fn push_field(&mut self, key: &str, value: &str) {
+        let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+
+        self.fields.entry(key.to_string())
+            .and_modify(|x| { x.push_str("; "); x.push_str(&value) })
+            .or_insert(value);
+    }
+
+    fn reference(&self) -> Reference {
+        Reference {
+            authors: self.authors.iter()
+                .map(|(family, given)| format!("{} {}", given, family).trim().to_string())
+                .collect(),
+            year: self.date.as_ref().and_then(|x| x.get(..4)).and_then(|x| x.parse().ok()),
+            doi: self.doi.clone(),
+            journal: self.container.clone().filter(|_| self.kind.starts_with("article")),
+            abstract_: self.abstract_.clone(),
+            keywords: self.keywords.clone(),
+        }
+    }
+
+    /// Entry in the hayagriva YAML format, the container becomes the parent
+    fn to_yaml(&self) -> Value {
+        let (kind, parent) = match self.kind.as_str() {
+            "article-journal" | "article-magazine" | "article" => ("article", Some("periodical")),
+            "article-newspaper" => ("article", Some("newspaper")),
+            "chapter" | "entry-encyclopedia" | "entry-dictionary" => ("chapter", Some("book")),
+            "paper-conference" => ("article", Some("proceedings")),
+            "book" => ("book", None),
+            "report" => ("report", None),
+            "thesis" => ("thesis", None),
+            "patent" => ("patent", None),
+            "manuscript" => ("manuscript", None),
+            "webpage" => ("web", None),
+            "post" | "post-weblog" => ("post", None),
+            _ => ("misc", None),
+        };
+
+        let mut entry = Mapping::new();
+        entry.insert("type".into(), kind.into());
+        entry.insert("title".into(), self.title.clone().into());
+
+        if !self.authors.is_empty() {
+            let authors = self.authors.iter()
+                .map(|(family, given)| match given.is_empty() {
+                    true => Value::from(family.clone()),
+                    false => Value::from(format!("{}, {}", family, given)),
+                })
+                .collect::<Vec<_>>();
+
+            entry.insert("author".into(), Value::Sequence(authors));
+        }
+        if let Some(date) = &self.date {
+            entry.insert("date".into(), date.clone().into());
+        }
+        if let Some(doi) = &self.doi {
+            let mut serial = Mapping::new();
+            serial.insert("doi".into(), doi.clone().into());
+            entry.insert("serial-number".into(), Value::Mapping(serial));
+        }
+        if let Some(url) = &self.url {
+            entry.insert("url".into(), url.clone().into());
+        }
+        if let Some(abstract_) = &self.abstract_ {
+            entry.insert("abstract".into(), abstract_.clone().into());
+        }
+        if let (Some(parent), Some(container)) = (parent, &self.container) {
+            let mut map = Mapping::new();
+            map.insert("type".into(), parent.into());
+            map.insert("title".into(), container.clone().into());
+            entry.insert("parent".into(), Value::Mapping(map));
+        }
+
+        Value::Mapping(entry)
+    }
+}
+
+/// Library of records, records rejected by hayagriva (e.g. malformed dates)
+/// are skipped
+pub(crate) fn library(records: &[Record]) -> Library {
+    let mut library = Library::new();
+
+    for record in records {
+        let mut map = Mapping::new();
+        map.insert(record.key.clone().into(), record.to_yaml());
+
+        if let Ok(entries) = serde_yaml::from_value::<Library>(Value::Mapping(map)) {
+            for entry in entries.iter() {
+                library.push(entry);
+            }
+        }
+    }
+
+    library
+}
+
+/// Convert records to literature notes, formatted with the CSL style
+pub(crate) fn notes(records: Vec<Record>, citations: &Citations, dependencies: &str) -> Vec<Note> {
+    let mut html = citations.format(&library(&records), BufWriteFormat::Html, false);
+
+    records.into_iter().map(|record| {
+        // resource field get precedence to attached file, get precedence to
+        // URL
+        let resource = record.resource.clone()
+            .or(record.file.as_ref().map(|x| format!("file:{}", x)))
+            .or(record.url.as_ref().map(|x| format!("url:{}", x)));
+
+        let normalized = record.fields.iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        Note {
+            header: record.title.clone(),
+            kind: Some(record.kind.clone()),
+            parent: None,
+            children: Vec::new(),
+            outgoing: Vec::new(),
+            external: Vec::new(),
+            incoming: Vec::new(),
+            html: html.shift_remove(&record.key).map(|x| format!("<div class=\"csl-entry\">{}</div>", x)).unwrap_or_default(),
+            resource,
+            hash: crate::utils::hash(&format!("{}{}{}\n{}", dependencies, record.kind, record.key, normalized)),
+            public: false,
+            cards: Vec::new(),
+            reference: Some(record.reference()),
+            anchors: None,
+            pages: None,
+            pdf: None,
+            metadata: record.fields.into_iter().map(|(k, v)| (k, toml::Value::String(v))).collect(),
+            span: record.span,
+            id: record.key,
+        }
+    }).collect()
+}
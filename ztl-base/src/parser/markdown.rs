@@ -1,23 +1,63 @@
 use comrak::nodes::{AstNode, NodeValue, NodeHeading};
 use comrak::{format_html, parse_document, Arena, Options};
 use indexmap::IndexMap;
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
 
-use crate::{Outgoing, LineColumn, Span, Note, error::*};
+use crate::{Outgoing, External, LinkKind, LineColumn, Span, Note, error::*};
+use super::NoteParser;
 
-pub(crate) fn analyze<'a>(arena: &'a Arena<AstNode<'a>>, content: &str, source: &PathBuf) -> Result<Vec<Note>> {
-    let root = parse_document(&arena, content, &Options::default());
+/// Markdown notes, separated by headings with lower-case key
+pub struct Markdown;
+
+impl NoteParser for Markdown {
+    fn extensions(&self) -> &[&str] {
+        &["md"]
+    }
+
+    /// Linked files are looked up relative to the working directory, see
+    /// [`Markdown::parse_file`]
+    fn parse(&self, content: &str, source: &PathBuf, report: &mut ParseReport) -> Result<Vec<Note>> {
+        let arena = Arena::new();
+
+        analyze(&arena, content, Path::new(""), source, report)
+    }
+
+    /// Linked files are looked up relative to the repository root
+    fn parse_file(&self, root: &Path, source: &PathBuf, report: &mut ParseReport) -> Result<Vec<Note>> {
+        let content = std::fs::read_to_string(root.join(source))?;
+        let arena = Arena::new();
+
+        analyze(&arena, &content, root, source, report)
+    }
+}
+
+type Metadata = IndexMap<String, toml::Value>;
+
+pub(crate) fn analyze<'a>(arena: &'a Arena<AstNode<'a>>, content: &str, root: &Path, source: &PathBuf, report: &mut ParseReport) -> Result<Vec<Note>> {
+    // front matter is either TOML (`+++`) or YAML (`---`) in the first lines
+    let mut options = Options::default();
+    options.extension.wikilinks_title_after_pipe = true;
+    options.extension.front_matter_delimiter = content.lines().next()
+        .map(|x| x.trim_end())
+        .filter(|x| *x == "---" || *x == "+++")
+        .map(|x| x.to_string());
+
+    let document = parse_document(&arena, content, &options);
 
     // first separate document into notes
-    let mut nodes: Vec<(String, String, Option<String>, &'a AstNode<'a>, Span, usize)> = vec![];
+    let mut nodes: Vec<(String, String, Option<String>, &'a AstNode<'a>, Span, usize, Metadata)> = vec![];
     let mut levels = Vec::new();
+    let mut defaults = Metadata::new();
+    let mut errors = ParseReport::empty();
 
-    for child in root.children() {
-        let (key, header, parent, level) = if let NodeValue::Heading(NodeHeading { level, .. }) = &child.data.borrow().value {
-            let label = match &child.first_child().unwrap().data.borrow().value {
-                NodeValue::Text(text) => text.clone(),
-                _ => panic!("No label available"),
-            };
+    for child in document.children() {
+        if let NodeValue::FrontMatter(text) = &child.data.borrow().value {
+            defaults = front_matter(text, source)?;
+            continue;
+        }
+
+        let (key, header, parent, level, attributes) = if let NodeValue::Heading(NodeHeading { level, .. }) = &child.data.borrow().value {
+            let (label, attributes) = split_attributes(&text_content(child));
 
             // check that the first character is ascii and lower-case
             if label.starts_with(|x: char| !x.is_ascii() || x.is_ascii_uppercase()) {
@@ -28,12 +68,26 @@ pub(crate) fn analyze<'a>(arena: &'a Arena<AstNode<'a>>, content: &str, source:
                 continue;
             }
 
-            let parts = label.splitn(2, " ").collect::<Vec<_>>();
-            let (key, header) = (parts[0].to_string(), parts[1].to_string());
+            let pos = child.data.borrow().sourcepos;
+            let heading = Span {
+                source: Some(source.clone()),
+                start: LineColumn { line: pos.start.line, column: Some(pos.start.column) },
+                end: LineColumn { line: pos.end.line, column: Some(pos.end.column) },
+            };
+
+            let (key, header) = match label.split_once(" ") {
+                Some((key, header)) => (key.to_string(), header.to_string()),
+                None => {
+                    errors.append(ParseReport::new(&heading, &heading, Code::MissingHeader, "heading has no header after the key"));
+                    continue;
+                },
+            };
 
             let level = *level as usize;
             if level > levels.len() + 1 {
-                panic!("Numbering not consistent!");
+                errors.append(ParseReport::new(&heading, &heading, Code::HeadingLevel,
+                    &format!("heading of level {} follows level {}", level, levels.len())));
+                continue;
             } else if level == levels.len() + 1 {
                 levels.push(key.clone());
             } else if level == levels.len() {
@@ -43,8 +97,8 @@ pub(crate) fn analyze<'a>(arena: &'a Arena<AstNode<'a>>, content: &str, source:
                 levels[level-1] = key.clone();
             };
 
-            let parent = levels.get(level - 2).map(|x: &String| x.to_string());
-            (key, header, parent, level)
+            let parent = level.checked_sub(2).and_then(|x| levels.get(x)).map(|x: &String| x.to_string());
+            (key, header, parent, level, attributes)
         } else {
             if nodes.len() > 0 {
                 nodes[nodes.len() - 1].3.append(child);
@@ -63,13 +117,15 @@ pub(crate) fn analyze<'a>(arena: &'a Arena<AstNode<'a>>, content: &str, source:
             end: Default::default(),
         };
 
-        let root = arena.alloc(NodeValue::Document.into());
-        root.append(child);
+        let node = arena.alloc(NodeValue::Document.into());
+        node.append(child);
 
         nodes.push(
-            (key.clone(), header, parent, root, span, level));
+            (key.clone(), header, parent, node, span, level, attributes));
     }
 
+    errors.as_err()?;
+
     // find ending of notes
     for i in 0..nodes.len() {
         let mut index = nodes.len();
@@ -87,47 +143,79 @@ pub(crate) fn analyze<'a>(arena: &'a Arena<AstNode<'a>>, content: &str, source:
             };
         } else {
             nodes[i].4.end = LineColumn {
-                line: content.split("\n").count() - 2,
+                line: content.split("\n").count().saturating_sub(2),
                 column: None,
             };
         };
     }
 
-    // parse notes to HTML and outgoing
-    let notes = nodes.into_iter().map(|(key, header, parent, node, span, _)| {
+    // parse notes to HTML and outgoing, invalid view modifiers are reported
+    // and the links kept without view
+    let notes = nodes.into_iter().map(|(key, header, parent, node, span, _, attributes)| {
         let mut outgoing: Vec<Outgoing> = vec![];
+        let mut external: Vec<External> = vec![];
+        let mut fallbacks = vec![];
+
+        // sub-headings, which are not notes themselves, are anchors
+        let anchors = node.children().skip(1)
+            .filter(|x| matches!(x.data.borrow().value, NodeValue::Heading(_)))
+            .map(|x| slug(&text_content(x)))
+            .collect::<Vec<_>>();
 
+        let note_span = span.clone();
         for node in node.descendants() {
             let pos = node.data.borrow().sourcepos;
 
-            if let NodeValue::Link(link) = &node.data.borrow().value {
-                let parts = link.url.split("#").collect::<Vec<_>>();
-                let (target, parts) = parts.split_at(1);
-                let view = parts.into_iter()
-                    .map(|x| x.splitn(2, "=").collect::<Vec<_>>())
-                    .map(|x| (x[0].to_string(), x[1].to_string()))
-                    .collect::<IndexMap<_, _>>();
-
-                let label = match &node.first_child().unwrap().data.borrow().value {
-                    NodeValue::Text(text) => text.clone(),
-                    _ => panic!("No label available"),
-                };
+            let span = Span {
+                source: None,
+                start: LineColumn {
+                    line: pos.start.line,
+                    column: Some(pos.start.column),
+                },
+                end: LineColumn {
+                    line: pos.end.line,
+                    column: Some(pos.end.column),
+                }
+            };
 
-                let span = Span {
-                    source: None,
-                    start: LineColumn {
-                        line: pos.start.line,
-                        column: Some(pos.start.column),
-                    },
-                    end: LineColumn {
-                        line: pos.end.line,
-                        column: Some(pos.end.column),
-                    }
+            let link = match &node.data.borrow().value {
+                NodeValue::Link(link) => Some((link.url.clone(), link.title.clone(), false)),
+                NodeValue::WikiLink(link) => Some((link.url.clone(), String::new(), true)),
+                _ => None,
+            };
+
+            // wiki links always refer to notes
+            let kind = link.as_ref()
+                .filter(|(_, _, is_wiki)| !is_wiki)
+                .and_then(|(url, _, _)| link_kind(url, root, source));
+
+            if let (Some((url, _, _)), Some(kind)) = (&link, kind) {
+                let target = match kind {
+                    LinkKind::File => resolve(url, source),
+                    _ => url.clone(),
                 };
 
+                external.push(External { kind, target, label: text_content(node), span });
+            } else if let Some((url, comment, is_wiki)) = link {
+                let (target, view) = super::split_view(&url);
+                let view = view.unwrap_or_else(|problem| {
+                    report.append(ParseReport::new(&note_span, &span, Code::InvalidView, &problem));
+                    None
+                });
+                let mut label = text_content(node);
+
+                // wiki link without label, falls back to header of target
+                if is_wiki && label == url {
+                    label = String::new();
+
+                    fallbacks.push((node, format!(
+                        "<a href=\"{}\" data-wikilink=\"true\" data-fallback=\"{}\">{}</a>",
+                        escape(&url), escape(&target), escape(&target))));
+                }
+
                 outgoing.push(Outgoing {
-                    target: target[0].to_string(),
-                    comment: link.title.clone(),
+                    target,
+                    comment,
                     label,
                     view,
                     span,
@@ -143,33 +231,279 @@ pub(crate) fn analyze<'a>(arena: &'a Arena<AstNode<'a>>, content: &str, source:
                 if let Some(text) = replace_by {
                     child.data.borrow_mut().value = NodeValue::HtmlInline(text);
 
-                    child.first_child().unwrap().detach();
+                    if let Some(label) = child.first_child() {
+                        label.detach();
+                    }
                 }
             }
         }
 
+        // replace links without label after traversal
+        for (node, html) in fallbacks {
+            while let Some(child) = node.first_child() {
+                child.detach();
+            }
+
+            node.data.borrow_mut().value = NodeValue::HtmlInline(html);
+        }
+
         let mut html = vec![];
         let mut opts = Options::default();
+        opts.extension.wikilinks_title_after_pipe = true;
         opts.render.unsafe_ = true;
         format_html(&node, &opts, &mut html).unwrap();
         let html = String::from_utf8(html).unwrap();
 
+        // attributes of heading take precedence over front matter
+        let mut metadata = defaults.clone();
+        metadata.extend(attributes);
+
+        let kind = metadata.shift_remove("kind").map(|x| match x {
+            toml::Value::String(x) => x,
+            x => x.to_string(),
+        });
+        let public = metadata.shift_remove("public")
+            .and_then(|x| x.as_bool()).unwrap_or(false);
+
         Note {
             id: key,
             header,
-            kind: None,
+            hash: crate::utils::hash(&format!("{}{:?}{:?}{}", html, kind, metadata, public)),
+            kind,
             parent,
             children: Vec::new(),
             outgoing,
+            external,
             incoming: Vec::new(),
-            hash: crate::utils::hash(&html),
             html,
             span,
             resource: None,
-            public: false,
+            public,
             cards: Vec::new(),
+            metadata,
+            reference: None,
+            anchors: Some(anchors),
+            pages: None,
+            pdf: None,
         }
     }).collect::<Vec<_>>();
 
     Ok(notes)
 }
+
+/// Concatenate text content of node and its descendants
+fn text_content<'a>(node: &'a AstNode<'a>) -> String {
+    node.descendants()
+        .filter_map(|x| match &x.data.borrow().value {
+            NodeValue::Text(text) => Some(text.clone()),
+            NodeValue::Code(code) => Some(code.literal.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Parse front matter block, including delimiters, to metadata
+fn front_matter(text: &str, source: &PathBuf) -> Result<Metadata> {
+    let mut lines = text.lines();
+    let delimiter = lines.next().unwrap_or("").trim_end();
+    let inner = lines.take_while(|x| x.trim_end() != delimiter)
+        .collect::<Vec<_>>().join("\n");
+
+    if inner.trim().is_empty() {
+        return Ok(Metadata::new());
+    }
+
+    // parse and find line of error, relative to beginning of file
+    let res = match delimiter {
+        "+++" => toml::from_str::<Metadata>(&inner)
+            .map_err(|err| (
+                err.message().to_string(),
+                err.span().map(|x| inner[..x.start].matches("\n").count() + 2))),
+        _ => serde_yaml::from_str::<Metadata>(&inner)
+            .map_err(|err| (
+                err.to_string(),
+                err.location().map(|x| x.line() + 1))),
+    };
+
+    res.map_err(|(reason, line)| {
+        let note = Span {
+            source: Some(source.clone()),
+            start: LineColumn { line: 1, column: None },
+            end: LineColumn { line: text.lines().count(), column: None },
+        };
+
+        let problem = match line {
+            Some(line) => Span {
+                source: Some(source.clone()),
+                start: LineColumn { line, column: Some(1) },
+                end: LineColumn { line, column: None },
+            },
+            None => note.clone(),
+        };
+
+        Error::Parse(ParseReport::new(&note, &problem, Code::InvalidFrontMatter, &format!("invalid front matter: {}", reason)))
+    })
+}
+
+/// Split trailing attribute block `{key=value ..}` from heading label
+fn split_attributes(label: &str) -> (String, Metadata) {
+    let trimmed = label.trim_end();
+    let start = match trimmed.rfind("{") {
+        Some(x) if trimmed.ends_with("}") => x,
+        _ => return (label.to_string(), Metadata::new()),
+    };
+
+    let inner = &trimmed[start + 1..trimmed.len() - 1];
+    let mut attributes = Metadata::new();
+    let mut chars = inner.chars().peekable();
+
+    loop {
+        // skip whitespace between attributes
+        while chars.peek().map(|x| x.is_whitespace()).unwrap_or(false) {
+            chars.next();
+        }
+
+        let mut key = String::new();
+        while let Some(x) = chars.next_if(|x| *x != '=' && !x.is_whitespace()) {
+            key.push(x);
+        }
+
+        if key.is_empty() {
+            break;
+        }
+
+        // attribute without value is a flag
+        if chars.next_if_eq(&'=').is_none() {
+            attributes.insert(key, toml::Value::Boolean(true));
+            continue;
+        }
+
+        let value = match chars.peek() {
+            Some('[') => {
+                chars.next();
+                let list: String = chars.by_ref().take_while(|x| *x != ']').collect();
+
+                toml::Value::Array(list.split(",")
+                    .map(str::trim)
+                    .filter(|x| !x.is_empty())
+                    .map(attribute_value)
+                    .collect())
+            },
+            Some('"') => {
+                chars.next();
+                toml::Value::String(chars.by_ref().take_while(|x| *x != '"').collect())
+            },
+            _ => {
+                let value: String = chars.by_ref().take_while(|x| !x.is_whitespace()).collect();
+                attribute_value(&value)
+            },
+        };
+
+        attributes.insert(key, value);
+    }
+
+    (trimmed[..start].trim_end().to_string(), attributes)
+}
+
+fn attribute_value(value: &str) -> toml::Value {
+    let value = value.trim_matches('"');
+
+    if let Ok(x) = value.parse::<bool>() {
+        toml::Value::Boolean(x)
+    } else if let Ok(x) = value.parse::<i64>() {
+        toml::Value::Integer(x)
+    } else if let Ok(x) = value.parse::<f64>() {
+        toml::Value::Float(x)
+    } else {
+        toml::Value::String(value.to_string())
+    }
+}
+
+/// Schemes of URLs without `//`, other destinations with a colon are keys of
+/// notes (e.g. `thm:compact` following LaTeX label conventions)
+const SCHEMES: &[&str] = &["doi", "isbn", "urn", "tel", "data"];
+
+/// Classify destination of link, returns `None` for links to notes
+///
+/// Destinations with `scheme://` or a known scheme are URLs, paths with a
+/// slash or naming an existing file next to the source are files.
+fn link_kind(url: &str, root: &Path, source: &Path) -> Option<LinkKind> {
+    let scheme = url.split_once(":")
+        .filter(|x| x.0.len() > 1 && x.0.starts_with(|c: char| c.is_ascii_alphabetic()))
+        .filter(|x| x.0.chars().all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.'));
+
+    match scheme.map(|(x, rest)| (x.to_lowercase(), rest)) {
+        Some((x, _)) if x == "mailto" => return Some(LinkKind::Mailto),
+        Some((x, _)) if x == "file" => return Some(LinkKind::File),
+        Some((x, rest)) if rest.starts_with("//") || SCHEMES.contains(&x.as_str()) => return Some(LinkKind::Url),
+        _ => {},
+    }
+
+    let path = url.split(['#', '?']).next().unwrap_or("");
+    if path.is_empty() {
+        return None;
+    }
+
+    let local = root.join(source.parent().unwrap_or(Path::new(""))).join(decode(path));
+    if path.contains("/") || local.is_file() {
+        Some(LinkKind::File)
+    } else {
+        None
+    }
+}
+
+/// Path of linked file relative to ZTL root, without fragment and query
+///
+/// Paths are relative to the source, or to the root with a leading slash.
+fn resolve(url: &str, source: &Path) -> String {
+    let url = url.strip_prefix("file://").or(url.strip_prefix("file:")).unwrap_or(url);
+    let path = decode(url.split(['#', '?']).next().unwrap_or(""));
+
+    let path = match path.strip_prefix("/") {
+        Some(path) => PathBuf::from(path),
+        None => source.parent().unwrap_or(Path::new("")).join(path),
+    };
+
+    // normalize without accessing the file system
+    let mut parts: Vec<Component> = Vec::new();
+    for part in path.components() {
+        match part {
+            Component::CurDir => {},
+            Component::ParentDir if matches!(parts.last(), Some(Component::Normal(_))) => { parts.pop(); },
+            part => parts.push(part),
+        }
+    }
+
+    parts.iter().collect::<PathBuf>().display().to_string()
+}
+
+/// Decode percent-encoded characters, e.g. `%20` for spaces
+fn decode(text: &str) -> String {
+    let mut bytes = Vec::new();
+    let mut rest = text.as_bytes();
+
+    while let Some((first, tail)) = rest.split_first() {
+        let hex = tail.get(..2)
+            .and_then(|x| std::str::from_utf8(x).ok())
+            .and_then(|x| u8::from_str_radix(x, 16).ok());
+
+        match (first, hex) {
+            (b'%', Some(x)) => { bytes.push(x); rest = &tail[2..]; },
+            _ => { bytes.push(*first); rest = tail; },
+        }
+    }
+
+    String::from_utf8_lossy(&bytes).to_string()
+}
+
+/// Anchor of heading, lower-case words joined by dashes as in GitHub
+fn slug(text: &str) -> String {
+    text.trim().to_lowercase().chars()
+        .filter(|x| x.is_alphanumeric() || *x == ' ' || *x == '-' || *x == '_')
+        .map(|x| if x == ' ' { '-' } else { x })
+        .collect()
+}
+
+fn escape(text: &str) -> String {
+    text.replace("&", "&amp;").replace("\"", "&quot;").replace("<", "&lt;").replace(">", "&gt;")
+}
@@ -1,32 +1,223 @@
 mod markdown;
 mod latex;
 mod bibtex;
+mod csl_json;
+mod ris;
+mod literature;
+mod pdf;
+#[cfg(feature = "zotero")]
+mod zotero;
+mod org;
+mod typst;
+mod tokenizer;
+mod citation;
 
-use std::{path::PathBuf, fs};
+use std::{path::{Path, PathBuf}, fs, fmt, sync::{Arc, atomic::{AtomicUsize, Ordering}}};
 use indexmap::{IndexSet, IndexMap};
 
 use itertools::Itertools;
-use glob::glob;
+use glob::{glob, Pattern};
 use glob_match::glob_match;
 
-use comrak::Arena;
-use comrak::nodes::AstNode;
+use crate::{Note, Key, View, File, FileSpan, Stamp, Span, LineColumn, notes::{Notes, Change}, error::*, config::Config, utils};
 
-use crate::{Note, Key, File, FileSpan, notes::{Notes, Change}, error::*, config::Config, utils};
+pub use markdown::Markdown;
+pub use latex::Latex;
+pub use bibtex::Bibtex;
+pub use csl_json::CslJson;
+pub use ris::Ris;
+#[cfg(feature = "zotero")]
+pub use zotero::Zotero;
+pub use org::Org;
+pub use typst::Typst;
+pub use citation::{Citations, load_library, BufWriteFormat};
+
+/// Source format, which can be separated into notes
+///
+/// Implement this trait and register it with [`Parsers`] to add support
+/// for further file formats.
+pub trait NoteParser: Send + Sync {
+    /// File extensions handled by this parser (without leading dot)
+    fn extensions(&self) -> &[&str];
+
+    /// Glob patterns of source files relative to the repository root, all
+    /// files with a handled extension by default
+    fn patterns(&self) -> Vec<String> {
+        self.extensions().iter().map(|x| format!("**/*.{}", x)).collect()
+    }
+
+    /// Separate content of a source file into notes
+    ///
+    /// Problems affecting single notes are added to the report, the other
+    /// notes of the file are still returned.
+    fn parse(&self, content: &str, source: &PathBuf, report: &mut ParseReport) -> Result<Vec<Note>>;
+
+    /// Read source file, relative to the repository root, and separate into
+    /// notes
+    fn parse_file(&self, root: &Path, source: &PathBuf, report: &mut ParseReport) -> Result<Vec<Note>> {
+        let content = fs::read_to_string(root.join(source))?;
+
+        self.parse(&content, source, report)
+    }
+
+    /// Render a changed note to HTML
+    ///
+    /// Returns `None` if the HTML was already produced while parsing.
+    fn render_html(&self, _note: &Note) -> Option<Result<String>> {
+        None
+    }
+
+    /// Files affecting the rendering of all notes (e.g. preamble)
+    ///
+    /// Notes are parsed and rendered again when any of them changes.
+    fn dependencies(&self) -> Vec<PathBuf> {
+        Vec::new()
+    }
+}
+
+/// Hash of the content of all dependencies, missing files are skipped
+pub(crate) fn dependency_hash(dependencies: &[PathBuf]) -> String {
+    let content = dependencies.iter()
+        .filter_map(|x| fs::read_to_string(x).ok())
+        .collect::<String>();
+
+    utils::hash(&content)
+}
+
+/// Substitute placeholders (e.g. `{file}`) of a command template for `sh -c`
+///
+/// Values are quoted, so that paths with spaces or shell metacharacters are
+/// passed as single arguments. Placeholders inside of values are kept.
+pub(crate) fn command(template: &str, values: &[(&str, &Path)]) -> String {
+    let (mut cmd, mut rest) = (String::new(), template);
+    while let Some(idx) = rest.find('{') {
+        cmd.push_str(&rest[..idx]);
+        rest = &rest[idx..];
+
+        let value = values.iter()
+            .find(|(name, _)| rest[1..].strip_prefix(name).map(|x| x.starts_with('}')).unwrap_or(false));
+
+        match value {
+            Some((name, value)) => {
+                cmd.push_str(&format!("'{}'", value.to_string_lossy().replace("'", "'\\''")));
+                rest = &rest[name.len() + 2..];
+            },
+            None => {
+                cmd.push('{');
+                rest = &rest[1..];
+            },
+        }
+    }
+
+    cmd.push_str(rest);
+    cmd
+}
+
+/// Registry of parsers, selected by file extension
+#[derive(Clone)]
+pub struct Parsers {
+    inner: Vec<Arc<dyn NoteParser>>,
+}
+
+impl Parsers {
+    pub fn empty() -> Self {
+        Parsers { inner: Vec::new() }
+    }
+
+    /// Parsers for all formats supported by ZTL
+    pub fn builtin(config: &Config) -> Self {
+        let mut parsers = Parsers::empty();
+        parsers.register(Bibtex::new(config));
+        parsers.register(CslJson::new(config));
+        parsers.register(Ris::new(config));
+        #[cfg(feature = "zotero")]
+        parsers.register(Zotero::new(config));
+        parsers.register(Markdown);
+        parsers.register(Latex::new(config));
+        parsers.register(Org);
+        parsers.register(Typst::new(config));
+
+        parsers
+    }
+
+    /// Add parser to registry, takes precedence over previously registered
+    /// parsers with same extension
+    pub fn register<P: NoteParser + 'static>(&mut self, parser: P) -> &mut Self {
+        self.inner.push(Arc::new(parser));
+        self
+    }
+
+    /// Find parser responsible for path
+    pub fn for_path(&self, path: &Path) -> Option<&Arc<dyn NoteParser>> {
+        let name = path.file_name().and_then(|x| x.to_str())?;
+
+        // extensions may contain dots themselves, e.g. `csl.json`
+        self.inner.iter().rev()
+            .filter(|x| x.extensions().iter().any(|ext| name.strip_suffix(ext).map(|x| x.ends_with('.')).unwrap_or(false)))
+            .next()
+    }
+
+    /// Glob patterns of all files, which depend on path
+    pub fn dependents(&self, path: &Path) -> Vec<String> {
+        self.inner.iter()
+            .filter(|x| x.dependencies().iter().any(|x| x == path))
+            .flat_map(|x| x.patterns())
+            .unique()
+            .collect()
+    }
+
+    /// Glob patterns of all source files, in order of registration
+    pub fn patterns(&self) -> Vec<String> {
+        self.inner.iter()
+            .flat_map(|x| x.patterns())
+            .unique()
+            .collect()
+    }
+
+    /// All registered extensions, in order of registration
+    pub fn extensions(&self) -> Vec<String> {
+        self.inner.iter()
+            .flat_map(|x| x.extensions().iter().map(|x| x.to_string()))
+            .unique()
+            .collect()
+    }
+}
+
+impl fmt::Debug for Parsers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Parsers").field("extensions", &self.extensions()).finish()
+    }
+}
 
 impl Notes {
-    pub fn update_files(mut self, pattern: &str, config: &Config, report: &mut ParseReport) -> Result<Self> {
-        let arena = Arena::new();
+    /// Update notes from all files in repository, which have a registered parser
+    ///
+    /// All formats are updated at once, so that links between notes of
+    /// different formats are checked after all of them are parsed.
+    pub fn update(self, parsers: &Parsers, config: &Config, report: &mut ParseReport) -> Result<Self> {
+        self.update_patterns(&parsers.patterns(), true, parsers, config, report)
+    }
 
-        // find all files in cache, matching the pattern
+    pub fn update_files(self, pattern: &str, parsers: &Parsers, config: &Config, report: &mut ParseReport) -> Result<Self> {
+        self.update_patterns(&[pattern.to_string()], false, parsers, config, report)
+    }
+
+    /// Update notes from files matching any pattern, with `all` every file in
+    /// cache is considered, so that files matching no pattern anymore (e.g. a
+    /// previously configured Zotero database) are removed
+    fn update_patterns(mut self, patterns: &[String], all: bool, parsers: &Parsers, config: &Config, report: &mut ParseReport) -> Result<Self> {
+        // find all files in cache, matching any pattern
         let files = self.files.iter()
-            .filter(|(_,v)| glob_match(pattern, v.source.to_str().unwrap()))
+            .filter(|(_,v)| all || patterns.iter().any(|x| glob_match(x, v.source.to_str().unwrap())))
             .map(|(_, v)| v.source.clone())
             .collect::<IndexSet<_>>();
 
-        // find all files in filesystem, matching the pattern
-        let files_local = glob(pattern).unwrap()
+        // find all files in filesystem, matching any pattern relative to root
+        let root = Pattern::escape(config.root.to_str().unwrap());
+        let files_local = patterns.iter()
+            .flat_map(|pattern| glob(&format!("{}/{}", root, pattern)).unwrap())
             .filter_map(|x| x.ok())
+            .filter_map(|x| x.strip_prefix(&config.root).ok().map(Path::to_path_buf))
             .filter(|x| !x.display().to_string().contains(".ztl"))
             .collect::<IndexSet<_>>();
 
@@ -43,10 +234,65 @@ impl Notes {
             self.files.remove(&hash).unwrap();
         }
 
+        // fingerprint of dependencies and configuration by parser
+        let config_content = fs::read_to_string(config.ztl_root().join("config")).unwrap_or_default();
+        let mut fingerprints: IndexMap<String, String> = IndexMap::new();
+        let mut fingerprint = |file: &Path| {
+            let parser = parsers.for_path(file);
+            let ext = parser.map(|x| x.extensions().join(",")).unwrap_or_default();
+            fingerprints.entry(ext).or_insert_with(|| {
+                let dependencies = parser.map(|x| x.dependencies()).unwrap_or_default();
+                utils::hash(format!("{}{}", dependency_hash(&dependencies), config_content))
+            }).clone()
+        };
+
+        // stamp new files, and find all files in both sets, which changed
+        // since the last update
+        let mut stamps = IndexMap::new();
+        let new_files = files_local.difference(&files).cloned().collect::<Vec<_>>();
+        for file in &new_files {
+            if let Some(stamp) = Stamp::of(&config.root.join(file), &fingerprint(file), None) {
+                stamps.insert(file.clone(), stamp);
+            }
+        }
+
+        let mut changed_files = Vec::new();
+        for file in files_local.intersection(&files) {
+            let hash = utils::hash(&file.to_str().unwrap());
+            let previous = self.files.get(&hash).unwrap().stamp.as_ref();
+
+            // notes with a replaced PDF resource are parsed again as well
+            let resource_changed = || self.files.get(&hash).unwrap().spans.values()
+                .filter_map(|x| self.notes.get(&x.target))
+                .any(|x| pdf::changed(x, &config.root));
+
+            if let Some(stamp) = Stamp::of(&config.root.join(file), &fingerprint(file), previous) {
+                let skip = previous.map(|x| x.unchanged(&stamp)).unwrap_or(false) && !resource_changed();
+                stamps.insert(file.clone(), stamp);
+
+                if skip {
+                    continue;
+                }
+            }
+
+            changed_files.push(file.clone());
+        }
+
+        // read and parse files in parallel, results are processed in order
+        let to_parse = new_files.iter().chain(&changed_files).collect::<Vec<_>>();
+        let mut parsed = parallel(&to_parse, |file| {
+            let mut report = ParseReport::empty();
+            (Self::parse_file(parsers, &config.root, file, &mut report), report)
+        }).into_iter();
+
         // record new files, add their notes as well
         let mut changed_notes = Vec::new();
-        for file in files_local.difference(&files) {
-            let notes = match Self::parse_file(&arena, &file, config) {
+        let mut notes_moved = IndexMap::new();
+        for file in &new_files {
+            let (notes, problems) = parsed.next().unwrap();
+            report.append(problems);
+
+            let notes = match notes {
                 Ok(x) => x,
                 Err(Error::Parse(x)) => {report.append(x); continue },
                 x => x?,
@@ -58,21 +304,23 @@ impl Notes {
 
             self.changes.push(Change::FileAdded(file.clone()));
 
+            // changes of notes are recorded once they are rendered, moved notes
+            // stay removed from their old file if rendering fails
             for note in &notes {
-                if notes_removed.contains_key(&note.id) {
-                    self.changes.push(Change::NoteMoved(note.id.clone(), notes_removed.get(&note.id).unwrap().clone(), file.clone()));
-                    notes_removed.remove(&note.id).unwrap();
-                } else {
-                    self.changes.push(Change::NoteAdded(note.id.clone()));
+                if let Some(from) = notes_removed.get(&note.id) {
+                    notes_moved.insert(note.id.clone(), from.clone());
                 }
             }
 
             changed_notes.extend(notes.into_iter());
         }
 
-        // and all remaining files in both sets
-        for file in files_local.intersection(&files) {
-            let notes = match Self::parse_file(&arena, &file, config) {
+        // and all changed files in both sets
+        for file in &changed_files {
+            let (notes, problems) = parsed.next().unwrap();
+            report.append(problems);
+
+            let notes = match notes {
                 Ok(x) => x,
                 Err(Error::Parse(x)) => {report.append(x); continue },
                 x => x?,
@@ -89,38 +337,83 @@ impl Notes {
             changed_notes.extend(notes.into_iter());
         }
 
-        let changed_keys = changed_notes.iter().map(|x| x.id.clone()).collect::<IndexSet<_>>();
+        let changed_notes = self.resolve_duplicates(changed_notes, &notes_removed, report);
+
+        let mut changed_keys = changed_notes.iter().map(|x| x.id.clone()).collect::<IndexSet<_>>();
         let notes_removed = notes_removed.keys().cloned()
             .collect::<IndexSet<String>>();
 
+        let states = changed_notes.iter().map(|note| {
+            match self.notes.get(&note.id) {
+                Some(old_note) => (false, old_note != note, old_note.html.clone(), changed_fields(old_note, note)),
+                None => (true, true, String::new(), Vec::new()),
+            }
+        }).collect::<Vec<_>>();
+
+        // named destinations of PDF resources are read once per change
+        let resources = parallel(&changed_notes, |note| pdf::read(note, self.notes.get(&note.id), &config.root))
+            .into_iter();
+
+        // render all changed notes first, as this may spawn external processes
+        let to_render = changed_notes.iter().zip(&states)
+            .filter(|(_, state)| state.1)
+            .map(|(note, _)| note)
+            .collect::<Vec<_>>();
+        let mut rendered = render_notes(parsers, &to_render).into_iter();
+
+        // notes linking to removed notes are checked again
+        let mut dependents = notes_removed.iter()
+            .filter_map(|x| self.notes.get(x))
+            .flat_map(|x| x.incoming.iter().cloned())
+            .collect::<IndexSet<_>>();
+
         // possibly update notes
-        for mut note in changed_notes {
-            let (new, changed, old_html) = {
-                match self.notes.get(&note.id) {
-                    Some(old_note) => (false, *old_note != note, old_note.html.clone()),
-                    None => (true, true, String::new()),
-                }
-            };
-            
-            note.public = note.span.source.as_ref()
+        let mut added = IndexSet::new();
+        for ((mut note, (new, changed, old_html, fields)), resource) in changed_notes.into_iter().zip(states).zip(resources) {
+            note.public |= note.span.source.as_ref()
                 .map(|x| x.display().to_string())
                 .map(|x| config.public.contains(&x)).unwrap_or(false);
 
-            if note.is_tex() {
-                if changed {
-                    note.html = latex::latex_to_html(&config, &note)?;
-                } else {
-                    note.html = old_html;
+            if let Some((stamp, destinations)) = resource {
+                if let Some((anchors, pages)) = destinations {
+                    note.anchors.get_or_insert_with(Vec::new).extend(anchors);
+                    note.pages = Some(pages);
                 }
+                note.pdf = Some(stamp);
+            }
+
+            if !changed {
+                note.html = old_html;
+            } else if let Some(html) = rendered.next().unwrap() {
+                note.html = match html {
+                    Ok(x) => x,
+                    Err(Error::Parse(x)) => {
+                        report.append(x);
+                        changed_keys.shift_remove(&note.id);
+                        continue
+                    },
+                    Err(x) => return Err(x),
+                };
+            }
+
+            // notes linking to a note with changed header, source or anchors
+            // are checked again, its incoming links are lost once replaced
+            let target = |x: &Note| (x.header.clone(), x.span.source.clone(), x.anchors.clone(), x.pages);
+            if let Some(old) = self.notes.get(&note.id).filter(|x| target(x) != target(&note)) {
+                dependents.extend(old.incoming.iter().cloned());
             }
 
             self.notes.insert(note.id.clone(), note.clone());
 
+            if let Some(from) = notes_moved.get(&note.id) {
+                self.changes.push(Change::NoteMoved(note.id.clone(), from.clone(), note.span.source.clone().unwrap()));
+            }
             if changed {
-                self.changes.push(Change::NoteChanged(note.id.clone()));
+                self.changes.push(Change::NoteChanged(note.id.clone(), fields));
             }
             if new {
                 self.changes.push(Change::NoteAdded(note.id.clone()));
+                added.insert(note.id.clone());
             }
         }
 
@@ -131,39 +424,200 @@ impl Notes {
             self.changes.push(Change::NoteRemoved(key.clone(), note));
         }
 
-        // update file spans for all modified keys
-        for (k, v) in self.spans(changed_keys, report)? {
+        // links to new notes were invalid before, hence not recorded as incoming
+        if !added.is_empty() {
+            dependents.extend(self.notes.values()
+                .filter(|x| x.outgoing.iter().any(|x| added.contains(&x.target)))
+                .map(|x| x.id.clone()));
+        }
+
+        // file spans are rebuilt for all notes in files of dependents
+        let keys = dependents.iter()
+            .filter_map(|x| self.notes.get(x)?.span.source.as_ref())
+            .map(|x| utils::hash(&x.to_str().unwrap()))
+            .unique()
+            .filter_map(|x| self.files.get(&x))
+            .flat_map(|x| x.spans.values().map(|x| x.target.clone()))
+            .chain(changed_keys)
+            .filter(|x| self.notes.contains_key(x))
+            .collect::<IndexSet<_>>();
+
+        let spans = self.spans(keys, &config.root, report)?;
+        for (k, v) in spans {
             self.files.insert(k, v);
         }
 
+        // files with errors are parsed again, to report them on every update
+        let failed = report.iter()
+            .filter(|x| x.severity == Severity::Error)
+            .flat_map(|x| [x.note_source(), x.reference_source()])
+            .flatten()
+            .cloned()
+            .collect::<IndexSet<_>>();
+
+        for (file, stamp) in stamps {
+            if let Some(entry) = self.files.get_mut(&utils::hash(&file.to_str().unwrap())) {
+                entry.stamp = (!failed.contains(&file)).then_some(stamp);
+            }
+        }
+
         Ok(self)
     }
 
-    fn parse_file<'a>(arena: &'a Arena<AstNode<'a>>, p: &PathBuf, config: &Config) -> Result<Vec<Note>> {
-        let content = fs::read_to_string(&p)?;
+    /// Keep a single note per key, all other definitions are reported
+    ///
+    /// The definition with the first source path and line is kept,
+    /// independent of the order of parsing. Notes of other files, which are
+    /// not parsed again, are considered as well and lose their key if they
+    /// come later.
+    fn resolve_duplicates(&mut self, notes: Vec<Note>, notes_removed: &IndexMap<Key, PathBuf>, report: &mut ParseReport) -> Vec<Note> {
+        let mut by_key: IndexMap<Key, Vec<Note>> = IndexMap::new();
+        for note in notes {
+            by_key.entry(note.id.clone()).or_default().push(note);
+        }
+
+        let mut resolved = Vec::new();
+        for (key, mut notes) in by_key {
+            // previous definition, which is neither removed nor parsed again
+            let previous = self.notes.get(&key)
+                .filter(|x| notes_removed.get(&key) != x.span.source.as_ref())
+                .filter(|x| notes.iter().all(|y| y.span.source != x.span.source))
+                .cloned();
 
-        match p.extension().and_then(|x| x.to_str()) {
-            Some("md") => markdown::analyze(&arena, &content, &p),
-            Some("bib") => bibtex::analyze(&content, &p),
-            Some("tex") => latex::analyze(config, &content, &p),
-            _ => panic!(""),
+            if notes.len() == 1 && previous.is_none() {
+                resolved.extend(notes);
+                continue;
+            }
+
+            // sorting is stable, notes without lines keep their order
+            let position = |x: &Note| (x.span.source.clone(), x.span.start.line);
+            notes.sort_by_key(position);
+
+            let keep_previous = matches!(&previous, Some(previous) if position(previous) < position(&notes[0]));
+            let (first, duplicates) = match keep_previous {
+                true => (previous.clone().unwrap(), notes.iter().collect::<Vec<_>>()),
+                false => (notes[0].clone(), notes.iter().skip(1).chain(previous.iter()).collect()),
+            };
+
+            // end line of note spans is the index of their last line
+            let note = Span { end: LineColumn { line: first.span.start.line.saturating_sub(1), column: None }, ..first_line(&first) };
+            for duplicate in duplicates {
+                report.append(ParseReport::new(&note, &first_line(duplicate),
+                    Code::DuplicateKey, &format!("note `{}` is already defined", key)));
+            }
+
+            // the previous definition loses its span, so that it is not
+            // removed together with its file later
+            if let Some(previous) = previous.filter(|_| !keep_previous) {
+                let hash = utils::hash(&previous.span.source.unwrap().display().to_string());
+                if let Some(file) = self.files.get_mut(&hash) {
+                    file.spans.retain(|_, x| x.target != key);
+                }
+            }
+
+            if !keep_previous {
+                resolved.push(notes.swap_remove(0));
+            }
         }
+
+        resolved
     }
 
-    pub fn spans(&self, keys: IndexSet<Key>, report: &mut ParseReport) -> Result<IndexMap<Key, File>> {
+    fn parse_file(parsers: &Parsers, root: &Path, p: &PathBuf, report: &mut ParseReport) -> Result<Vec<Note>> {
+        parsers.for_path(p)
+            .ok_or_else(|| Error::UnknownFormat(p.clone()))?
+            .parse_file(root, p, report)
+    }
+
+    pub fn spans(&self, keys: IndexSet<Key>, root: &Path, report: &mut ParseReport) -> Result<IndexMap<Key, File>> {
         keys.into_iter().map(|x| self.notes.get(&x).unwrap())
             .sorted_by(|a,b| Ord::cmp(&a.span.source, &b.span.source))
             .chunk_by(|n| n.span.source.clone().unwrap())
             .into_iter()
             .map(|(file, notes)| {
-                let spans = notes.into_iter().map(|note|
-                    note.outgoing_spans(&self, report).map(|s| (format!("{}:{}", note.start_line(), note.end_line()), s))
-                ).collect::<Result<IndexMap<_, FileSpan>>>()?;
+                // notes without lines (e.g. of databases) are identified by key
+                let spans = notes.into_iter().map(|note| {
+                    let range = match note.span.has_lines() {
+                        true => format!("{}:{}", note.start_line(), note.end_line()),
+                        false => note.id.clone(),
+                    };
+                    note.outgoing_spans(&self, root, report).map(|s| (range, s))
+                }).collect::<Result<IndexMap<_, FileSpan>>>()?;
 
                 let hash = utils::hash(&file.display().to_string());
 
-                Ok((hash, File { source: PathBuf::from(file), spans }))
+                Ok((hash, File { source: PathBuf::from(file), stamp: None, spans }))
             })
             .collect()
     }
 }
+
+/// Span of the first line of a note, e.g. its heading
+fn first_line(note: &Note) -> Span {
+    Span {
+        source: note.span.source.clone(),
+        start: LineColumn { line: note.span.start.line, column: None },
+        end: LineColumn { line: note.span.start.line, column: None },
+    }
+}
+
+/// Split link target into key and view modifier
+///
+/// The modifier follows the first `#`, see [`View`] for the syntax. Invalid
+/// modifiers are returned as problem, to be reported at the span of the link.
+pub(crate) fn split_view(url: &str) -> (Key, std::result::Result<Option<View>, String>) {
+    match url.split_once("#") {
+        Some((target, "")) => (target.to_string(), Ok(None)),
+        Some((target, modifier)) => (target.to_string(), modifier.parse().map(Some)),
+        None => (url.to_string(), Ok(None)),
+    }
+}
+
+/// Metadata fields, which differ between two versions of a note
+fn changed_fields(old: &Note, new: &Note) -> Vec<String> {
+    old.metadata.keys().chain(new.metadata.keys())
+        .unique()
+        .filter(|x| old.metadata.get(*x) != new.metadata.get(*x))
+        .cloned()
+        .collect()
+}
+
+/// Render notes to HTML concurrently, with at most one worker per core
+///
+/// Results are returned in the order of the notes.
+fn render_notes(parsers: &Parsers, notes: &[&Note]) -> Vec<Option<Result<String>>> {
+    parallel(notes, |note| parsers.for_path(note.span.source.as_ref().unwrap())
+        .and_then(|x| x.render_html(note)))
+}
+
+/// Apply function to all items on all available cores, results are in the
+/// order of the items
+fn parallel<T: Sync, R: Send>(items: &[T], f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let workers = std::thread::available_parallelism()
+        .map(|x| x.get())
+        .unwrap_or(1)
+        .min(items.len());
+
+    let next = AtomicUsize::new(0);
+    let mut results = std::thread::scope(|s| {
+        let handles = (0..workers).map(|_| s.spawn(|| {
+            let mut results = Vec::new();
+            loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let item = match items.get(i) {
+                    Some(x) => x,
+                    None => break results,
+                };
+
+                results.push((i, f(item)));
+            }
+        })).collect::<Vec<_>>();
+
+        handles.into_iter()
+            .flat_map(|x| x.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+
+    results.sort_by_key(|x| x.0);
+    results.into_iter().map(|x| x.1).collect()
+}
@@ -0,0 +1,309 @@
+use std::path::PathBuf;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use indexmap::IndexMap;
+
+use crate::{Outgoing, LineColumn, Span, Note, error::*};
+use super::NoteParser;
+
+static RE_HEADLINE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^(\*+)\s+(.*?)\s*$").unwrap());
+static RE_TAGS: Lazy<Regex> = Lazy::new(|| Regex::new(r"\s+:([\w@#%:]+):$").unwrap());
+static RE_PROPERTY: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\s*:([^:\s]+):\s*(.*?)\s*$").unwrap());
+static RE_LINK: Lazy<Regex> = Lazy::new(|| Regex::new(r"\[\[([^\]]+)\](?:\[([^\]]*)\])?\]").unwrap());
+static RE_SCHEME: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[a-zA-Z][a-zA-Z0-9+-]*:").unwrap());
+
+/// Org notes, separated by headlines with lower-case key
+pub struct Org;
+
+impl NoteParser for Org {
+    fn extensions(&self) -> &[&str] {
+        &["org"]
+    }
+
+    fn parse(&self, content: &str, source: &PathBuf, report: &mut ParseReport) -> Result<Vec<Note>> {
+        analyze(content, source, report)
+    }
+}
+
+#[derive(Debug)]
+struct OrgNote {
+    key: String,
+    header: String,
+    parent: Option<String>,
+    level: usize,
+    start: usize,
+    end: usize,
+    metadata: IndexMap<String, toml::Value>,
+    body: Vec<(usize, String)>,
+}
+
+pub(crate) fn analyze(content: &str, source: &PathBuf, report: &mut ParseReport) -> Result<Vec<Note>> {
+    let lines = content.split("\n").collect::<Vec<_>>();
+
+    // first separate document into notes
+    let mut nodes: Vec<OrgNote> = Vec::new();
+    let mut levels: Vec<(usize, String)> = Vec::new();
+    let mut in_drawer = false;
+
+    for (i, line) in lines.iter().enumerate() {
+        if let Some(caps) = RE_HEADLINE.captures(line) {
+            let level = caps.get(1).unwrap().as_str().len();
+            let mut title = caps.get(2).unwrap().as_str().to_string();
+
+            // check that the first character is ascii and lower-case
+            if title.starts_with(|x: char| !x.is_ascii() || x.is_ascii_uppercase()) || title.is_empty() {
+                if let Some(node) = nodes.last_mut() {
+                    node.body.push((i, line.to_string()));
+                }
+
+                continue;
+            }
+
+            let mut metadata = IndexMap::new();
+            if let Some(tags) = RE_TAGS.captures(&title) {
+                let tags = tags.get(1).unwrap().as_str().split(":")
+                    .filter(|x| !x.is_empty())
+                    .map(|x| toml::Value::String(x.to_string()))
+                    .collect();
+
+                metadata.insert("tags".to_string(), toml::Value::Array(tags));
+                title = RE_TAGS.replace(&title, "").to_string();
+            }
+
+            let (key, header) = match title.split_once(" ") {
+                Some((key, header)) => (key.to_string(), header.trim().to_string()),
+                None => (title.clone(), String::new()),
+            };
+
+            // headlines may skip levels in org, so the parent is the closest
+            // headline with lower level
+            while levels.last().map(|x| x.0 >= level).unwrap_or(false) {
+                levels.pop();
+            }
+            let parent = levels.last().map(|x| x.1.clone());
+            levels.push((level, key.clone()));
+
+            nodes.push(OrgNote {
+                key, header, parent, level, metadata,
+                start: i + 1,
+                end: 0,
+                body: Vec::new(),
+            });
+
+            in_drawer = false;
+            continue;
+        }
+
+        let node = match nodes.last_mut() {
+            Some(x) => x,
+            None => continue,
+        };
+
+        // property drawer directly below the headline
+        if line.trim() == ":PROPERTIES:" && node.body.iter().all(|x| x.1.trim().is_empty()) {
+            in_drawer = true;
+            continue;
+        }
+
+        if in_drawer {
+            if line.trim() == ":END:" {
+                in_drawer = false;
+            } else if let Some(caps) = RE_PROPERTY.captures(line) {
+                node.metadata.insert(
+                    caps.get(1).unwrap().as_str().to_lowercase(),
+                    toml::Value::String(caps.get(2).unwrap().as_str().to_string()));
+            }
+
+            continue;
+        }
+
+        node.body.push((i, line.to_string()));
+    }
+
+    // find ending of notes
+    for i in 0..nodes.len() {
+        let index = ((i+1)..nodes.len())
+            .filter(|j| nodes[*j].level <= nodes[i].level)
+            .next();
+
+        nodes[i].end = match index {
+            Some(j) => nodes[j].start - 2,
+            None => lines.len().saturating_sub(2),
+        };
+    }
+
+    // parse notes to HTML and outgoing, invalid view modifiers are reported
+    // and the links kept without view
+    let notes = nodes.into_iter().map(|node| {
+        let span = Span {
+            source: Some(source.clone()),
+            start: LineColumn { line: node.start, column: None },
+            end: LineColumn { line: node.end, column: None },
+        };
+
+        let mut outgoing = Vec::new();
+        for (i, line) in &node.body {
+            for caps in RE_LINK.captures_iter(line) {
+                let url = caps.get(1).unwrap().as_str();
+                if RE_SCHEME.is_match(url) {
+                    continue;
+                }
+
+                let (target, view) = super::split_view(url);
+                let label = caps.get(2).map(|x| x.as_str()).unwrap_or("").to_string();
+                let m = caps.get(0).unwrap();
+
+                let link = Span {
+                    source: None,
+                    start: LineColumn {
+                        line: i + 1,
+                        column: Some(line[..m.start()].chars().count() + 1),
+                    },
+                    end: LineColumn {
+                        line: i + 1,
+                        column: Some(line[..m.end()].chars().count()),
+                    },
+                };
+
+                let view = view.unwrap_or_else(|problem| {
+                    report.append(ParseReport::new(&span, &link, Code::InvalidView, &problem));
+                    None
+                });
+
+                outgoing.push(Outgoing {
+                    target,
+                    comment: String::new(),
+                    label,
+                    view,
+                    span: link,
+                });
+            }
+        }
+
+        let html = to_html(&node.body.iter().map(|x| x.1.as_str()).collect::<Vec<_>>());
+
+        // headline and properties are part of the hash, so that their
+        // changes are recorded
+        let hash = crate::utils::hash(&format!("{}{}{:?}", node.header, html, node.metadata));
+
+        Note {
+            id: node.key,
+            header: node.header,
+            kind: None,
+            parent: node.parent,
+            children: Vec::new(),
+            outgoing,
+            external: Vec::new(),
+            incoming: Vec::new(),
+            hash,
+            html,
+            span,
+            resource: None,
+            public: false,
+            cards: Vec::new(),
+            metadata: node.metadata,
+            reference: None,
+            anchors: None,
+            pages: None,
+            pdf: None,
+        }
+    }).collect();
+
+    Ok(notes)
+}
+
+/// Render the body of an org note to HTML
+///
+/// Only covers paragraphs, plain lists, blocks and inline markup, this
+/// is enough for preview and publishing.
+fn to_html(lines: &[&str]) -> String {
+    let mut html = String::new();
+    let mut paragraph: Vec<String> = Vec::new();
+    let mut in_list = false;
+    let mut block: Option<Vec<&str>> = None;
+
+    let flush = |html: &mut String, paragraph: &mut Vec<String>| {
+        if !paragraph.is_empty() {
+            html.push_str(&format!("<p>{}</p>\n", paragraph.join("\n")));
+            paragraph.clear();
+        }
+    };
+
+    for line in lines {
+        let trimmed = line.trim();
+
+        if let Some(content) = block.as_mut() {
+            if trimmed.to_lowercase().starts_with("#+end_") {
+                html.push_str(&format!("<pre><code>{}</code></pre>\n", escape(&content.join("\n"))));
+                block = None;
+            } else {
+                content.push(line);
+            }
+
+            continue;
+        }
+
+        if trimmed.to_lowercase().starts_with("#+begin_") {
+            flush(&mut html, &mut paragraph);
+            block = Some(Vec::new());
+            continue;
+        }
+
+        // skip comments, keywords and nested headlines
+        if trimmed.starts_with("#") || RE_HEADLINE.is_match(line) {
+            continue;
+        }
+
+        if let Some(item) = trimmed.strip_prefix("- ").or(trimmed.strip_prefix("+ ")) {
+            flush(&mut html, &mut paragraph);
+            if !in_list {
+                html.push_str("<ul>\n");
+                in_list = true;
+            }
+            html.push_str(&format!("<li>{}</li>\n", inline(item)));
+            continue;
+        }
+
+        if in_list {
+            html.push_str("</ul>\n");
+            in_list = false;
+        }
+
+        if trimmed.is_empty() {
+            flush(&mut html, &mut paragraph);
+        } else {
+            paragraph.push(inline(trimmed));
+        }
+    }
+
+    if in_list {
+        html.push_str("</ul>\n");
+    }
+    flush(&mut html, &mut paragraph);
+
+    html
+}
+
+fn inline(text: &str) -> String {
+    static RE_MARKUP: Lazy<Vec<(Regex, &'static str)>> = Lazy::new(|| vec![
+        (Regex::new(r"(^|[\s(])\*([^\s*](?:[^*]*[^\s*])?)\*").unwrap(), "$1<b>$2</b>"),
+        (Regex::new(r"(^|[\s(])/([^\s/](?:[^/]*[^\s/])?)/").unwrap(), "$1<i>$2</i>"),
+        (Regex::new(r"(^|[\s(])[=~]([^\s=~](?:[^=~]*[^\s=~])?)[=~]").unwrap(), "$1<code>$2</code>"),
+    ]);
+
+    let mut text = escape(text);
+    for (re, rep) in RE_MARKUP.iter() {
+        text = re.replace_all(&text, *rep).to_string();
+    }
+
+    RE_LINK.replace_all(&text, |caps: &regex::Captures| {
+        let url = caps.get(1).unwrap().as_str();
+        let label = caps.get(2).map(|x| x.as_str()).unwrap_or(url);
+
+        format!("<a href=\"{}\">{}</a>", url, label)
+    }).to_string()
+}
+
+fn escape(text: &str) -> String {
+    text.replace("&", "&amp;").replace("<", "&lt;").replace(">", "&gt;")
+}
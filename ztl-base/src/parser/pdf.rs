@@ -0,0 +1,89 @@
+//! Anchors of PDF resources from their named destinations
+
+use std::path::{Path, PathBuf};
+use lopdf::{Dictionary, Document};
+
+use crate::{Note, Stamp};
+
+/// Path of PDF file attached to note with `file:` resource
+pub(crate) fn resource(note: &Note) -> Option<PathBuf> {
+    note.resource.as_ref()?
+        .strip_prefix("file:")
+        .filter(|x| x.to_lowercase().ends_with(".pdf"))
+        .map(PathBuf::from)
+}
+
+/// Stamp of the PDF resource of a note, with its named destinations and
+/// number of pages
+///
+/// The file is only read again if it differs from the resource of the
+/// previous version of the note.
+pub(crate) fn read(note: &Note, previous: Option<&Note>, root: &Path) -> Option<(Stamp, Option<(Vec<String>, usize)>)> {
+    let path = root.join(resource(note)?);
+    let previous = previous.filter(|x| x.resource == note.resource);
+    let stamp = Stamp::of(&path, "", previous.and_then(|x| x.pdf.as_ref()))?;
+
+    let destinations = match previous.filter(|x| x.pdf.as_ref().map(|x| x.unchanged(&stamp)).unwrap_or(false)) {
+        Some(previous) => previous.pages.map(|x| (previous.anchors.clone().unwrap_or_default(), x)),
+        None => destinations(&path),
+    };
+
+    Some((stamp, destinations))
+}
+
+/// Whether the PDF resource of a note was added, replaced or removed since
+/// it was read
+pub(crate) fn changed(note: &Note, root: &Path) -> bool {
+    resource(note).map(|path| match (&note.pdf, Stamp::of(&root.join(path), "", note.pdf.as_ref())) {
+        (Some(old), Some(new)) => !old.unchanged(&new),
+        (None, None) => false,
+        _ => true,
+    }).unwrap_or(false)
+}
+
+/// Named destinations and number of pages of PDF file
+///
+/// Returns `None` if the file cannot be read, e.g. it is encrypted.
+pub(crate) fn destinations(path: &Path) -> Option<(Vec<String>, usize)> {
+    let doc = Document::load(path).ok()?;
+    let catalog = doc.catalog().ok()?;
+    let mut names = Vec::new();
+
+    // PDF 1.1 keeps destinations in a dictionary, later versions in a name tree
+    if let Ok(dests) = catalog.get_deref(b"Dests", &doc).and_then(|x| x.as_dict()) {
+        names.extend(dests.iter().map(|(k, _)| String::from_utf8_lossy(k).to_string()));
+    }
+
+    let tree = catalog.get_deref(b"Names", &doc)
+        .and_then(|x| x.as_dict())
+        .and_then(|x| x.get_deref(b"Dests", &doc))
+        .and_then(|x| x.as_dict());
+
+    if let Ok(tree) = tree {
+        name_tree(&doc, tree, &mut names, 0);
+    }
+
+    Some((names, doc.get_pages().len()))
+}
+
+/// Collect keys of name tree, depth is limited as malformed trees may contain
+/// cycles
+fn name_tree(doc: &Document, node: &Dictionary, names: &mut Vec<String>, depth: usize) {
+    if depth > 32 {
+        return;
+    }
+
+    if let Ok(kids) = node.get_deref(b"Kids", doc).and_then(|x| x.as_array()) {
+        for kid in kids {
+            if let Ok(kid) = doc.dereference(kid).and_then(|x| x.1.as_dict()) {
+                name_tree(doc, kid, names, depth + 1);
+            }
+        }
+    }
+
+    if let Ok(pairs) = node.get_deref(b"Names", doc).and_then(|x| x.as_array()) {
+        names.extend(pairs.iter().step_by(2)
+            .filter_map(|x| x.as_str().ok())
+            .map(|x| String::from_utf8_lossy(x).to_string()));
+    }
+}
@@ -0,0 +1,211 @@
+use std::path::PathBuf;
+
+use crate::{LineColumn, Span, Note, error::ParseReport, error::*};
+use crate::config::Config;
+use super::NoteParser;
+use super::literature::{self, Record};
+
+/// Literature notes from RIS records, formatted with CSL style
+pub struct Ris {
+    config: Config,
+}
+
+impl Ris {
+    pub fn new(config: &Config) -> Self {
+        Ris { config: config.clone() }
+    }
+}
+
+impl NoteParser for Ris {
+    fn extensions(&self) -> &[&str] {
+        &["ris"]
+    }
+
+    fn parse(&self, content: &str, source: &PathBuf, report: &mut ParseReport) -> Result<Vec<Note>> {
+        let (citations, dependencies) = literature::citations(&self.config)?;
+        let records = records(content, source, report)?;
+
+        Ok(literature::notes(records, &citations, &dependencies))
+    }
+
+    fn dependencies(&self) -> Vec<PathBuf> {
+        literature::style_files(&self.config)
+    }
+}
+
+/// Split line into tag and value, e.g. `AU  - Doe, Jane`
+///
+/// The value of `ER` may be missing together with the trailing space.
+fn tag(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim_end();
+    let tag = line.get(..2)?;
+
+    if !tag.chars().all(|x| x.is_ascii_uppercase() || x.is_ascii_digit()) {
+        return None;
+    }
+
+    match line.get(2..) {
+        Some("  -") => Some((tag, "")),
+        Some(rest) => rest.strip_prefix("  - ").map(|x| (tag, x.trim())),
+        None => None,
+    }
+}
+
+/// Convert records between `TY` and `ER` tags
+///
+/// Tags outside of records, unclosed records and records without key are
+/// added to the report, all other records are returned. Lines without tag
+/// continue the value of the previous tag.
+pub(crate) fn records(content: &str, source: &PathBuf, report: &mut ParseReport) -> Result<Vec<Record>> {
+    let line_span = |i: usize, line: &str| Span {
+        source: Some(source.clone()),
+        start: LineColumn { line: i + 1, column: Some(1) },
+        end: LineColumn { line: i + 1, column: Some(line.chars().count().max(1)) },
+    };
+
+    let mut records = Vec::new();
+
+    // tags of currently open record and its first line
+    let mut current: Option<(Vec<(&str, String)>, usize)> = None;
+
+    let lines = content.split("\n").collect::<Vec<_>>();
+    for (i, line) in lines.iter().enumerate() {
+        let line = line.trim_start_matches('\u{feff}');
+
+        match (tag(line), &mut current) {
+            (Some(("TY", kind)), None) => current = Some((vec![("TY", kind.to_string())], i)),
+            (Some(("TY", kind)), Some((_, start))) => {
+                let note = Span {
+                    source: Some(source.clone()),
+                    start: LineColumn { line: *start + 1, column: None },
+                    end: LineColumn { line: i - 1, column: None },
+                };
+                report.append(ParseReport::new(&note, &line_span(i, line), Code::InvalidRis, "RIS record starts before previous record is closed with `ER`"));
+
+                current = Some((vec![("TY", kind.to_string())], i));
+            },
+            (Some(("ER", _)), Some(_)) => {
+                let (tags, start) = current.take().unwrap();
+                let mut record = Record {
+                    span: Span {
+                        source: Some(source.clone()),
+                        start: LineColumn { line: start + 1, column: None },
+                        end: LineColumn { line: i, column: None },
+                    },
+                    ..Default::default()
+                };
+
+                for (tag, value) in tags {
+                    add_field(&mut record, tag, &value);
+                }
+
+                if record.key.is_empty() {
+                    record.key = fallback_key(&record);
+                }
+
+                if record.key.is_empty() {
+                    report.append(ParseReport::new(&record.span, &line_span(start, lines[start]), Code::InvalidRis, "RIS record without `ID` or author, year and title"));
+                } else {
+                    records.push(record);
+                }
+            },
+            (Some(x), Some((tags, _))) => tags.push((x.0, x.1.to_string())),
+            (None, _) if line.trim().is_empty() => {},
+            // continued value of previous tag
+            (None, Some((tags, _))) => {
+                let value = &mut tags.last_mut().unwrap().1;
+                value.push(' ');
+                value.push_str(line.trim());
+            },
+            (_, None) => {
+                let span = line_span(i, line);
+                report.append(ParseReport::new(&span, &span, Code::InvalidRis, "expected RIS tag `TY  - ` to start record"));
+            },
+        }
+    }
+
+    if let Some((_, start)) = current {
+        let note = Span {
+            source: Some(source.clone()),
+            start: LineColumn { line: start + 1, column: None },
+            end: LineColumn { line: lines.len() - 1, column: None },
+        };
+        report.append(ParseReport::new(&note, &line_span(start, lines[start]), Code::InvalidRis, "RIS record is never closed with `ER`"));
+    }
+
+    Ok(records)
+}
+
+/// Apply tag to record, unknown tags are only kept as metadata
+fn add_field(record: &mut Record, tag: &str, value: &str) {
+    match tag {
+        "TY" => record.kind = kind_of(value).to_string(),
+        "ID" => record.key = value.to_string(),
+        "TI" | "T1" | "CT" if record.title.is_empty() => record.title = value.to_string(),
+        "AU" | "A1" => record.authors.push(match value.split_once(",") {
+            Some((family, given)) => (family.trim().to_string(), given.trim().to_string()),
+            None => (value.to_string(), String::new()),
+        }),
+        "PY" | "Y1" | "DA" if record.date.is_none() => {
+            let parts = value.split("/")
+                .take_while(|x| !x.is_empty())
+                .map_while(|x| x.parse::<u32>().ok())
+                .collect::<Vec<_>>();
+
+            record.date = match parts.as_slice() {
+                [] => None,
+                [year] => Some(format!("{:04}", year)),
+                [year, month] => Some(format!("{:04}-{:02}", year, month)),
+                [year, month, day, ..] => Some(format!("{:04}-{:02}-{:02}", year, month, day)),
+            };
+        },
+        "DO" => record.doi = Some(value.to_string()),
+        // full journal name is preferred to abbreviations
+        "JF" => record.container = Some(value.to_string()),
+        "T2" | "JO" | "JA" | "BT" if record.container.is_none() => record.container = Some(value.to_string()),
+        "AB" | "N2" if record.abstract_.is_none() => record.abstract_ = Some(value.to_string()),
+        "KW" => record.keywords.push(value.to_string()),
+        "UR" if record.url.is_none() => record.url = Some(value.to_string()),
+        "L1" if record.file.is_none() =>
+            record.file = Some(value.strip_prefix("file://").unwrap_or(value).to_string()),
+        _ => {},
+    }
+
+    if tag != "TY" && tag != "ID" {
+        record.push_field(&tag.to_lowercase(), value);
+    }
+}
+
+/// CSL item type of RIS reference type
+fn kind_of(ty: &str) -> &'static str {
+    match ty {
+        "JOUR" | "JFULL" | "EJOUR" => "article-journal",
+        "MGZN" => "article-magazine",
+        "NEWS" => "article-newspaper",
+        "BOOK" | "EBOOK" | "EDBOOK" => "book",
+        "CHAP" | "ECHAP" => "chapter",
+        "CONF" | "CPAPER" => "paper-conference",
+        "THES" => "thesis",
+        "RPRT" => "report",
+        "ELEC" | "WEB" => "webpage",
+        "BLOG" => "post-weblog",
+        "PAT" => "patent",
+        "UNPB" | "MANSCPT" => "manuscript",
+        _ => "document",
+    }
+}
+
+/// Key from family name of first author, year and first word of title,
+/// e.g. `doe2020deep`
+fn fallback_key(record: &Record) -> String {
+    let (family, year, word) = match (record.authors.first(), &record.date, record.title.split_whitespace().next()) {
+        (Some(author), Some(date), Some(word)) => (&author.0, date.get(..4).unwrap_or(date), word),
+        _ => return String::new(),
+    };
+
+    format!("{}{}{}", family, year, word)
+        .chars()
+        .filter(|x| x.is_alphanumeric())
+        .flat_map(|x| x.to_lowercase())
+        .collect()
+}
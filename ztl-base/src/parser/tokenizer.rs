@@ -0,0 +1,203 @@
+//! Tokenizer for LaTeX sources
+//!
+//! Splits a document into control sequences and their arguments, while
+//! skipping comments and escaped characters. Positions are byte offsets
+//! into the source and can be converted to line and column.
+
+use crate::LineColumn;
+
+/// Environments, whose content is not interpreted by TeX
+const VERBATIM: &[&str] = &["verbatim", "verbatim*", "Verbatim", "lstlisting", "minted", "comment"];
+
+pub(crate) struct Tokenizer<'a> {
+    src: &'a str,
+    pos: usize,
+    lines: Vec<usize>,
+}
+
+impl<'a> Tokenizer<'a> {
+    pub(crate) fn new(src: &'a str) -> Self {
+        let lines = std::iter::once(0)
+            .chain(src.match_indices("\n").map(|x| x.0 + 1))
+            .collect();
+
+        Tokenizer { src, pos: 0, lines }
+    }
+
+    pub(crate) fn src(&self) -> &'a str {
+        self.src
+    }
+
+    /// Line (1-based) and column (1-based, in characters) of byte offset
+    pub(crate) fn position(&self, offset: usize) -> LineColumn {
+        let line = self.lines.partition_point(|x| *x <= offset);
+        let column = self.src[self.lines[line - 1]..offset].chars().count() + 1;
+
+        LineColumn { line, column: Some(column) }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.src[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    /// Skip comment until end of line, the newline is kept
+    fn skip_comment(&mut self) {
+        self.pos = self.src[self.pos..].find("\n")
+            .map(|x| self.pos + x)
+            .unwrap_or(self.src.len());
+    }
+
+    /// Skip whitespace and comments, but stop at empty lines
+    fn skip_whitespace(&mut self) {
+        let mut newlines = 0;
+        while let Some(c) = self.peek() {
+            match c {
+                '%' => self.skip_comment(),
+                '\n' if newlines > 0 => return,
+                '\n' => { newlines += 1; self.bump(); },
+                c if c.is_whitespace() => { self.bump(); },
+                _ => return,
+            }
+        }
+    }
+
+    /// Advance to next control word, returns its name and starting offset
+    ///
+    /// Control symbols (e.g. `\%`, `\{`) and comments are skipped.
+    pub(crate) fn next_command(&mut self) -> Option<(&'a str, usize)> {
+        while let Some(c) = self.bump() {
+            match c {
+                '%' => self.skip_comment(),
+                '\\' => {
+                    let start = self.pos - 1;
+                    let len = self.src[self.pos..]
+                        .find(|x: char| !x.is_ascii_alphabetic())
+                        .unwrap_or(self.src.len() - self.pos);
+
+                    if len == 0 {
+                        // control symbol, skip escaped character
+                        self.bump();
+                        continue;
+                    }
+
+                    let name = &self.src[self.pos..self.pos + len];
+                    self.pos += len;
+
+                    return Some((name, start));
+                },
+                _ => {},
+            }
+        }
+
+        None
+    }
+
+    /// Parse argument delimited by `open` and `close`
+    ///
+    /// Returns byte range of the content without delimiters, for optional
+    /// arguments the position is restored if there is none. Brackets
+    /// inside of braces are ignored.
+    pub(crate) fn argument(&mut self, open: char, close: char) -> Option<(usize, usize)> {
+        let before = self.pos;
+        self.skip_whitespace();
+
+        if self.peek() != Some(open) {
+            self.pos = before;
+            return None;
+        }
+        self.bump();
+
+        let start = self.pos;
+        let mut depth = 0;
+        while let Some(c) = self.peek() {
+            match c {
+                '\\' => { self.bump(); },
+                '%' => { self.skip_comment(); continue },
+                '{' => depth += 1,
+                '}' if depth > 0 => depth -= 1,
+                c if c == close && depth == 0 => {
+                    let end = self.pos;
+                    self.bump();
+                    return Some((start, end));
+                },
+                _ => {},
+            }
+
+            self.bump();
+        }
+
+        // argument never closed
+        self.pos = before;
+        None
+    }
+
+    /// Skip content of verbatim environment and its closing command
+    pub(crate) fn skip_verbatim(&mut self, env: &str) -> bool {
+        let end = format!("\\end{{{}}}", env);
+
+        match self.src[self.pos..].find(&end) {
+            Some(x) => { self.pos += x + end.len(); true },
+            None => { self.pos = self.src.len(); false },
+        }
+    }
+
+    /// Skip inline verbatim `\verb|..|` after the command name
+    pub(crate) fn skip_verb(&mut self) {
+        if self.peek() == Some('*') {
+            self.bump();
+        }
+
+        if let Some(delim) = self.bump() {
+            while let Some(c) = self.bump() {
+                if c == delim || c == '\n' {
+                    break;
+                }
+            }
+        }
+    }
+
+    pub(crate) fn offset(&self) -> usize {
+        self.pos
+    }
+}
+
+pub(crate) fn is_verbatim(env: &str) -> bool {
+    VERBATIM.contains(&env)
+}
+
+/// Split key-value list `key=value, ..` at top-level commas
+///
+/// Values are trimmed, enclosing braces removed and whitespace collapsed.
+pub(crate) fn key_values(src: &str) -> Vec<(String, String)> {
+    let mut parts = Vec::new();
+    let (mut depth, mut last) = (0, 0);
+
+    for (i, c) in src.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&src[last..i]);
+                last = i + 1;
+            },
+            _ => {},
+        }
+    }
+    parts.push(&src[last..]);
+
+    parts.into_iter()
+        .filter_map(|x| x.split_once("="))
+        .map(|(k, v)| {
+            let v = v.trim();
+            let v = v.strip_prefix("{").and_then(|x| x.strip_suffix("}")).unwrap_or(v);
+
+            (k.trim().to_string(), v.split_whitespace().collect::<Vec<_>>().join(" "))
+        })
+        .collect()
+}
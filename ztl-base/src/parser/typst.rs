@@ -0,0 +1,388 @@
+use std::path::PathBuf;
+use std::process::Command;
+use std::io::Write;
+use std::fs;
+use once_cell::sync::Lazy;
+use indexmap::IndexMap;
+use scraper::{Html, Selector};
+use regex::Regex;
+
+use crate::{Outgoing, LineColumn, Span, Note, error::*};
+use crate::config::Config;
+use super::NoteParser;
+
+/// Typst notes from function calls with `label` argument and content block
+pub struct Typst {
+    config: Config,
+}
+
+impl Typst {
+    pub fn new(config: &Config) -> Self {
+        Typst { config: config.clone() }
+    }
+}
+
+impl NoteParser for Typst {
+    fn extensions(&self) -> &[&str] {
+        &["typ"]
+    }
+
+    fn parse(&self, content: &str, source: &PathBuf, report: &mut ParseReport) -> Result<Vec<Note>> {
+        let dependencies = super::dependency_hash(&self.dependencies());
+
+        analyze(&self.config, content, source, &dependencies, report)
+    }
+
+    fn render_html(&self, note: &Note) -> Option<Result<String>> {
+        Some(typst_to_html(&self.config, note))
+    }
+
+    fn dependencies(&self) -> Vec<PathBuf> {
+        self.config.typst_preamble().into_iter().collect()
+    }
+}
+
+#[derive(Default, Debug)]
+struct TypstNote {
+    env_kind: String,
+    label: String,
+    title: String,
+    start: usize,
+    end: usize,
+    parent: Option<String>,
+}
+
+/// Open bracket in the document, either a note body or any other content block
+enum Bracket {
+    Note(TypstNote),
+    Content,
+}
+
+pub fn typst_to_html(config: &Config, note: &Note) -> Result<String> {
+    let preamble = match config.typst_preamble() {
+        Some(path) => fs::read_to_string(&path)?,
+        None => String::new(),
+    };
+    let preamble_len = preamble.lines().count();
+
+    let tmp_dir = tempfile::TempDir::new()?;
+
+    let out_file = tmp_dir.path().join("main.typ");
+    let html_file = tmp_dir.path().join("main.html");
+    let mut f = fs::File::create(&out_file)?;
+
+    f.write(preamble.as_bytes())?;
+    if !preamble.is_empty() && !preamble.ends_with("\n") {
+        f.write(b"\n")?;
+    }
+    f.write(note.html.as_bytes())?;
+
+    let cmd = super::command(&config.typst.build, &[("file", &out_file), ("output", &html_file)]);
+
+    let out = Command::new("sh")
+        .arg("-c")
+        .arg(&cmd)
+        .current_dir(tmp_dir.path())
+        .output()?;
+
+    if !out.status.success() {
+        static RE_ERR: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?m)^error: (.*)$").unwrap());
+        static RE_POS: Lazy<Regex> = Lazy::new(|| Regex::new(r"main\.typ:(\d+):(\d+)").unwrap());
+
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        let reason = RE_ERR.captures(&stderr)
+            .map(|x| x.get(1).unwrap().as_str().to_string())
+            .unwrap_or("typst problem".to_string());
+
+        let pos = RE_POS.captures(&stderr)
+            .map(|x| (x[1].parse::<usize>().unwrap(), x[2].parse::<usize>().unwrap()));
+
+        let note_span = note.span.clone();
+        let problem = match pos {
+            Some((line, column)) if line <= preamble_len => {
+                let preamble = config.typst_preamble().unwrap();
+
+                return Err(Error::Parse(ParseReport::new(
+                    &note_span,
+                    &Span {
+                        source: Some(preamble),
+                        start: LineColumn { line, column: Some(column) },
+                        end: LineColumn { line, column: None },
+                    },
+                    Code::RenderFailed,
+                    &format!("preamble error: {}", reason)
+                )));
+            },
+            Some((line, column)) => {
+                let line = note_span.start.line + line - preamble_len - 1;
+
+                Span {
+                    source: note_span.source.clone(),
+                    start: LineColumn { line, column: Some(column) },
+                    end: LineColumn { line, column: None },
+                }
+            },
+            None => note_span.clone(),
+        };
+
+        Err(Error::Parse(ParseReport::new(&note_span, &problem, Code::RenderFailed, &reason)))
+    } else {
+        let cont = fs::read_to_string(&html_file)?;
+        let document = Html::parse_document(&cont);
+
+        let body = document.select(&Selector::parse("body").unwrap()).next()
+            .map(|x| x.inner_html())
+            .unwrap_or(cont);
+
+        Ok(body.trim().to_string())
+    }
+}
+
+pub(crate) fn analyze(_config: &Config, content: &str, source: &PathBuf, dependencies: &str, report: &mut ParseReport) -> Result<Vec<Note>> {
+    static RE_CALL: Lazy<Regex> = Lazy::new(|| Regex::new(r"^#([a-zA-Z][\w-]*)\(").unwrap());
+    static RE_ARG: Lazy<Regex> = Lazy::new(|| Regex::new(r#"(\w+)\s*:\s*"((?:[^"\\]|\\.)*)""#).unwrap());
+    static RE_TRAILING: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[ \t]*<([\w\-.:]+)>").unwrap());
+
+    let lines = LineStarts::new(content);
+    let bytes = content.as_bytes();
+
+    let mut stack: Vec<Bracket> = Vec::new();
+    let mut notes = Vec::new();
+    let mut pending: Option<TypstNote> = None;
+
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => { i += 2; continue; },
+            // skip line comments
+            b'/' if bytes.get(i+1) == Some(&b'/') => {
+                i = content[i..].find("\n").map(|x| i + x).unwrap_or(bytes.len());
+                continue;
+            },
+            // skip block comments
+            b'/' if bytes.get(i+1) == Some(&b'*') => {
+                i = content[i..].find("*/").map(|x| i + x + 2).unwrap_or(bytes.len());
+                continue;
+            },
+            // skip raw text
+            b'`' => {
+                let ticks = content[i..].bytes().take_while(|x| *x == b'`').count();
+                let delim = "`".repeat(ticks);
+                i = content[i+ticks..].find(&delim).map(|x| i + ticks + x + ticks).unwrap_or(bytes.len());
+                continue;
+            },
+            b'#' => {
+                if let Some(caps) = RE_CALL.captures(&content[i..]) {
+                    let args_start = i + caps.get(0).unwrap().end();
+                    let args_end = match matching(content, args_start, b'(', b')') {
+                        Some(x) => x,
+                        None => { i += 1; continue; },
+                    };
+
+                    // only function calls with trailing content block can be notes
+                    if bytes.get(args_end + 1) == Some(&b'[') {
+                        let mut note = TypstNote::default();
+                        note.env_kind = caps[1].to_string();
+
+                        for arg in RE_ARG.captures_iter(&content[args_start..args_end]) {
+                            match &arg[1] {
+                                "label" => note.label = arg[2].to_string(),
+                                "name" => note.title = arg[2].to_string(),
+                                _ => {},
+                            }
+                        }
+
+                        note.start = lines.position(i).0;
+                        pending = Some(note);
+                        i = args_end + 1;
+                        continue;
+                    }
+                }
+            },
+            b'[' => {
+                match pending.take() {
+                    Some(note) => stack.push(Bracket::Note(note)),
+                    None => stack.push(Bracket::Content),
+                }
+            },
+            b']' => {
+                if let Some(Bracket::Note(mut note)) = stack.pop() {
+                    // label may also be attached after the block as `<key>`
+                    if note.label.is_empty() {
+                        if let Some(caps) = RE_TRAILING.captures(&content[i+1..]) {
+                            note.label = caps[1].to_string();
+                            i += caps.get(0).unwrap().end();
+                        }
+                    }
+
+                    note.end = lines.position(i).0 - 1;
+
+                    if !note.label.is_empty() {
+                        note.parent = stack.iter().rev().filter_map(|x| match x {
+                            Bracket::Note(x) if !x.label.is_empty() => Some(x.label.clone()),
+                            _ => None,
+                        }).next();
+
+                        notes.push(note);
+                    }
+                }
+            },
+            _ => {},
+        }
+
+        i += 1;
+    }
+
+    // invalid view modifiers of links are reported, the links kept without view
+    let notes = notes.into_iter().map(|note| {
+        let span = Span {
+            source: Some(source.clone()),
+            start: LineColumn { line: note.start, column: None },
+            end: LineColumn { line: note.end, column: None },
+        };
+
+        let start = lines.offset(note.start);
+        let end = lines.offset(note.end + 2).unwrap_or(content.len());
+        let body = &content[start.unwrap_or(0)..end];
+
+        let outgoing = links(body, note.start, &span, report);
+
+        Ok(Note {
+            id: note.label,
+            header: note.title,
+            kind: Some(note.env_kind),
+            parent: note.parent,
+            children: Vec::new(),
+            outgoing,
+            external: Vec::new(),
+            incoming: Vec::new(),
+            span,
+            resource: None,
+            hash: crate::utils::hash(&format!("{}{}", dependencies, body)),
+            html: body.to_string(),
+            public: false,
+            cards: Vec::new(),
+            metadata: IndexMap::new(),
+            reference: None,
+            anchors: None,
+            pages: None,
+            pdf: None,
+        })
+    }).collect::<Result<Vec<_>>>()?;
+
+    Ok(notes)
+}
+
+/// Extract references `@key` and link calls `#r("key")[label]` from note body
+fn links(body: &str, first_line: usize, note: &Span, report: &mut ParseReport) -> Vec<Outgoing> {
+    static RE_REF: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?:^|[^\w@])(@([\w\-:]+(?:\.[\w\-:]+)*))(?:\[([^\]]*)\])?").unwrap());
+    static RE_LINK: Lazy<Regex> = Lazy::new(|| Regex::new(r#"#r\("([^"]*)"\)(?:\[([^\]]*)\])?"#).unwrap());
+
+    let mut outgoing = Vec::new();
+    for (l, line) in body.split("\n").enumerate() {
+        // ignore commented out part of the line
+        let line = match line.find("//") {
+            Some(x) if !line[..x].ends_with(":") => &line[..x],
+            _ => line,
+        };
+
+        let span = |start: usize, end: usize| Span {
+            source: None,
+            start: LineColumn {
+                line: first_line + l,
+                column: Some(line[..start].chars().count() + 1),
+            },
+            end: LineColumn {
+                line: first_line + l,
+                column: Some(line[..end].chars().count()),
+            },
+        };
+
+        for caps in RE_REF.captures_iter(line) {
+            let m = caps.get(1).unwrap();
+            let end = caps.get(0).unwrap().end();
+            let (target, view) = super::split_view(&caps[2]);
+            let link = span(m.start(), end);
+
+            outgoing.push(Outgoing {
+                target,
+                comment: String::new(),
+                label: caps.get(3).map(|x| x.as_str()).unwrap_or("").to_string(),
+                view: view.unwrap_or_else(|problem| {
+                    report.append(ParseReport::new(note, &link, Code::InvalidView, &problem));
+                    None
+                }),
+                span: link,
+            });
+        }
+
+        for caps in RE_LINK.captures_iter(line) {
+            let m = caps.get(0).unwrap();
+            let (target, view) = super::split_view(&caps[1]);
+            let link = span(m.start(), m.end());
+
+            outgoing.push(Outgoing {
+                target,
+                comment: String::new(),
+                label: caps.get(2).map(|x| x.as_str()).unwrap_or("").to_string(),
+                view: view.unwrap_or_else(|problem| {
+                    report.append(ParseReport::new(note, &link, Code::InvalidView, &problem));
+                    None
+                }),
+                span: link,
+            });
+        }
+    }
+
+    outgoing.sort_by_key(|x| (x.span.start.line, x.span.start.column));
+    outgoing
+}
+
+/// Find position of closing bracket, starting after the opening one
+fn matching(content: &str, start: usize, open: u8, close: u8) -> Option<usize> {
+    let bytes = content.as_bytes();
+    let mut depth = 1;
+    let mut in_string = false;
+
+    let mut i = start;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b'"' => in_string = !in_string,
+            x if x == open && !in_string => depth += 1,
+            x if x == close && !in_string => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            },
+            _ => {},
+        }
+
+        i += 1;
+    }
+
+    None
+}
+
+/// Byte offsets of line beginnings
+struct LineStarts(Vec<usize>);
+
+impl LineStarts {
+    fn new(content: &str) -> Self {
+        LineStarts(std::iter::once(0)
+            .chain(content.match_indices("\n").map(|x| x.0 + 1))
+            .collect())
+    }
+
+    /// Line (1-based) of byte offset
+    fn position(&self, offset: usize) -> (usize, usize) {
+        let line = self.0.partition_point(|x| *x <= offset);
+        (line, offset - self.0[line - 1])
+    }
+
+    /// Byte offset of line (1-based)
+    fn offset(&self, line: usize) -> Option<usize> {
+        self.0.get(line - 1).cloned()
+    }
+}
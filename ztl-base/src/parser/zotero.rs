@@ -0,0 +1,263 @@
+use std::path::{Path, PathBuf};
+use indexmap::IndexMap;
+use rusqlite::{Connection, OpenFlags};
+
+use crate::{Span, Note, error::*};
+use crate::config::Config;
+use super::NoteParser;
+use super::literature::{self, Record};
+
+/// Literature notes from a local Zotero library, formatted with CSL style
+///
+/// The database is opened read-only and immutable, hence it can be read
+/// while Zotero is running. Only the configured database is read, other
+/// SQLite files in the repository are neither searched nor parsed.
+pub struct Zotero {
+    config: Config,
+}
+
+impl Zotero {
+    pub fn new(config: &Config) -> Self {
+        Zotero { config: config.clone() }
+    }
+}
+
+impl NoteParser for Zotero {
+    fn extensions(&self) -> &[&str] {
+        &["sqlite"]
+    }
+
+    /// Only the configured database, which is usually outside of the
+    /// repository
+    fn patterns(&self) -> Vec<String> {
+        self.config.zotero_database().into_iter()
+            .map(|x| glob::Pattern::escape(&x.display().to_string()))
+            .collect()
+    }
+
+    /// Databases are binary, see [`Zotero::parse_file`]
+    fn parse(&self, _content: &str, _source: &PathBuf, _report: &mut ParseReport) -> Result<Vec<Note>> {
+        Ok(Vec::new())
+    }
+
+    fn parse_file(&self, root: &Path, source: &PathBuf, _report: &mut ParseReport) -> Result<Vec<Note>> {
+        if self.config.zotero_database().as_ref() != Some(source) {
+            return Ok(Vec::new());
+        }
+
+        let (citations, dependencies) = literature::citations(&self.config)?;
+        let records = records(&root.join(source), source, self.config.zotero_storage().as_deref())?;
+
+        Ok(literature::notes(records, &citations, &dependencies))
+    }
+
+    fn dependencies(&self) -> Vec<PathBuf> {
+        literature::style_files(&self.config)
+    }
+}
+
+/// Open SQLite database without locking, Zotero keeps an exclusive lock
+fn open(path: &Path) -> rusqlite::Result<Connection> {
+    let path = path.display().to_string()
+        .replace("%", "%25")
+        .replace("?", "%3f")
+        .replace("#", "%23");
+
+    Connection::open_with_flags(format!("file:{}?immutable=1", path),
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_NO_MUTEX)
+}
+
+/// Convert regular items of the library to records
+///
+/// Attachments, notes and items in trash are skipped. Keys are taken from
+/// Better BibTeX if available, otherwise from the `Citation Key` in the extra
+/// field or the Zotero item key. Without storage directory no attachments are
+/// resolved.
+///
+/// Databases have no lines, hence spans only refer to the source of the
+/// database, relative to root.
+pub(crate) fn records(database: &Path, source: &Path, storage: Option<&Path>) -> Result<Vec<Record>> {
+    let err = |e: rusqlite::Error| Error::Zotero(database.to_path_buf(), e.to_string());
+    let conn = open(database).map_err(err)?;
+
+    let items = query(&conn, "SELECT items.itemID, items.key, itemTypes.typeName FROM items
+        JOIN itemTypes USING (itemTypeID)
+        WHERE itemTypes.typeName NOT IN ('attachment', 'note', 'annotation')
+            AND items.itemID NOT IN (SELECT itemID FROM deletedItems)
+        ORDER BY items.itemID", 3).map_err(err)?;
+
+    let fields = query(&conn, "SELECT itemData.itemID, fields.fieldName, itemDataValues.value FROM itemData
+        JOIN fields USING (fieldID)
+        JOIN itemDataValues USING (valueID)
+        ORDER BY itemData.itemID, fields.fieldID", 3).map_err(err)?;
+
+    let creators = query(&conn, "SELECT itemCreators.itemID, creatorTypes.creatorType, creators.lastName, creators.firstName FROM itemCreators
+        JOIN creators USING (creatorID)
+        JOIN creatorTypes USING (creatorTypeID)
+        ORDER BY itemCreators.itemID, itemCreators.orderIndex", 4).map_err(err)?;
+
+    let tags = query(&conn, "SELECT itemTags.itemID, tags.name FROM itemTags
+        JOIN tags USING (tagID)
+        ORDER BY itemTags.itemID, tags.name", 2).map_err(err)?;
+
+    // PDF attachments get precedence to other files
+    let attachments = query(&conn, "SELECT itemAttachments.parentItemID, items.key, itemAttachments.path FROM itemAttachments
+        JOIN items USING (itemID)
+        WHERE itemAttachments.parentItemID IS NOT NULL AND itemAttachments.path IS NOT NULL
+            AND itemAttachments.itemID NOT IN (SELECT itemID FROM deletedItems)
+        ORDER BY itemAttachments.contentType = 'application/pdf' DESC, itemAttachments.itemID", 3).map_err(err)?;
+
+    let fields = group(fields);
+    let creators = group(creators);
+    let tags = group(tags);
+    let attachments = group(attachments);
+    let citation_keys = better_bibtex(database);
+
+    let mut records = Vec::new();
+    for item in items {
+        let (id, key, kind) = (&item[0], &item[1], &item[2]);
+        let fields = fields.get(id).map(|x| x.as_slice()).unwrap_or_default();
+        let field = |name: &str| fields.iter().find(|x| x[0] == name).map(|x| x[1].clone());
+
+        let mut record = Record {
+            kind: kind_of(kind).to_string(),
+            title: field("title").unwrap_or_default(),
+            date: field("date").and_then(|x| date(&x)),
+            doi: field("DOI"),
+            container: field("publicationTitle").or(field("bookTitle")).or(field("proceedingsTitle")),
+            abstract_: field("abstractNote"),
+            url: field("url"),
+            span: Span::without_lines(source),
+            ..Default::default()
+        };
+
+        for field in fields {
+            match field[0].as_str() {
+                "date" => record.push_field("date", record.date.clone().as_deref().unwrap_or("")),
+                name => record.push_field(name, &field[1]),
+            }
+        }
+
+        for creator in creators.get(id).into_iter().flatten() {
+            let name = match creator[2].is_empty() {
+                true => creator[1].clone(),
+                false => format!("{}, {}", creator[1], creator[2]),
+            };
+
+            record.push_field(&creator[0], &name);
+            if creator[0] == "author" {
+                record.authors.push((creator[1].clone(), creator[2].clone()));
+            }
+        }
+
+        record.keywords = tags.get(id).into_iter().flatten().map(|x| x[0].clone()).collect();
+        if !record.keywords.is_empty() {
+            record.push_field("tags", &record.keywords.join(", "));
+        }
+
+        record.file = storage.and_then(|storage| attachments.get(id)?.iter()
+            .find_map(|x| attachment(storage, &x[0], &x[1])));
+        if let Some(file) = record.file.clone() {
+            record.push_field("file", &file);
+        }
+
+        // pinned keys of Better BibTeX are kept in the extra field
+        let extra = field("extra").unwrap_or_default();
+        let pinned = extra.lines()
+            .find_map(|x| x.strip_prefix("Citation Key:"))
+            .map(|x| x.trim().to_string());
+
+        record.key = citation_keys.get(key).cloned()
+            .or(field("citationKey"))
+            .or(pinned)
+            .unwrap_or(key.clone());
+
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+/// Rows of query as text, `NULL` values become empty strings
+fn query(conn: &Connection, sql: &str, columns: usize) -> rusqlite::Result<Vec<Vec<String>>> {
+    let mut stmt = conn.prepare(sql)?;
+    let rows = stmt.query_map([], |row| {
+        (0..columns).map(|i| row.get::<_, rusqlite::types::Value>(i).map(|x| match x {
+            rusqlite::types::Value::Text(x) => x,
+            rusqlite::types::Value::Integer(x) => x.to_string(),
+            rusqlite::types::Value::Real(x) => x.to_string(),
+            _ => String::new(),
+        })).collect()
+    })?;
+
+    rows.collect()
+}
+
+/// Group rows by item ID in first column
+fn group(rows: Vec<Vec<String>>) -> IndexMap<String, Vec<Vec<String>>> {
+    let mut groups: IndexMap<String, Vec<Vec<String>>> = IndexMap::new();
+    for mut row in rows {
+        let id = row.remove(0);
+        groups.entry(id).or_default().push(row);
+    }
+
+    groups
+}
+
+/// Citation keys of Better BibTeX by Zotero item key, empty if the plugin
+/// is not installed
+fn better_bibtex(database: &Path) -> IndexMap<String, String> {
+    open(&database.with_file_name("better-bibtex.sqlite"))
+        .and_then(|conn| query(&conn, "SELECT itemKey, citationKey FROM citationkey", 2))
+        .map(|rows| rows.into_iter().map(|x| (x[0].clone(), x[1].clone())).collect())
+        .unwrap_or_default()
+}
+
+/// Path of attachment, stored files are prefixed with `storage:` and placed
+/// in a directory named after the attachment key
+///
+/// Files relative to the base directory of linked attachments are skipped,
+/// as the base directory is only known to Zotero.
+fn attachment(storage: &Path, key: &str, path: &str) -> Option<String> {
+    if let Some(name) = path.strip_prefix("storage:") {
+        return Some(storage.join(key).join(name).display().to_string());
+    }
+
+    Path::new(path).is_absolute().then(|| path.to_string())
+}
+
+/// Date as `year[-month[-day]]`, stored as `2020-05-00 May 2020` with
+/// unknown parts set to zero
+fn date(value: &str) -> Option<String> {
+    let parts = value.split_whitespace().next()?
+        .split("-")
+        .take_while(|x| x.chars().any(|x| x != '0'))
+        .collect::<Vec<_>>();
+
+    match parts.is_empty() {
+        true => None,
+        false => Some(parts.join("-")),
+    }
+}
+
+/// CSL item type of Zotero item type
+fn kind_of(kind: &str) -> &'static str {
+    match kind {
+        "journalArticle" => "article-journal",
+        "magazineArticle" => "article-magazine",
+        "newspaperArticle" => "article-newspaper",
+        "book" => "book",
+        "bookSection" => "chapter",
+        "encyclopediaArticle" => "entry-encyclopedia",
+        "dictionaryEntry" => "entry-dictionary",
+        "conferencePaper" => "paper-conference",
+        "thesis" => "thesis",
+        "report" => "report",
+        "webpage" => "webpage",
+        "blogPost" => "post-weblog",
+        "forumPost" => "post",
+        "patent" => "patent",
+        "manuscript" => "manuscript",
+        _ => "document",
+    }
+}
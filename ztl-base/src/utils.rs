@@ -1,3 +1,4 @@
+use std::path::{Path, PathBuf, Component};
 use sha2::Digest;
 
 pub fn line_col_to_byte_offset(text: &str, line: usize, column: usize) -> Option<usize> {
@@ -149,7 +150,7 @@ pub fn line_col_to_byte_offset(text: &str, line: usize, column: usize) -> Option
 //    let _ = std::io::stdout().write_all(&buf);
 //}
 
-pub fn hash(content: &str) -> String {
+pub fn hash(content: impl AsRef<[u8]>) -> String {
     let mut sha256 = sha2::Sha256::new();
     sha256.update(content);
     format!("{:X}", sha256.finalize())
@@ -170,3 +171,49 @@ pub fn hash(content: &str) -> String {
 //        }
 //    }
 //}
+
+/// Relative path from base to path, absolute paths are kept if base is relative
+pub fn diff_paths<P, B>(path: P, base: B) -> Option<PathBuf>
+where
+    P: AsRef<Path>,
+    B: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let base = base.as_ref();
+
+    if path.is_absolute() != base.is_absolute() {
+        if path.is_absolute() {
+            Some(PathBuf::from(path))
+        } else {
+            None
+        }
+    } else {
+        let mut ita = path.components();
+        let mut itb = base.components();
+        let mut comps: Vec<Component> = vec![];
+        loop {
+            match (ita.next(), itb.next()) {
+                (None, None) => break,
+                (Some(a), None) => {
+                    comps.push(a);
+                    comps.extend(ita.by_ref());
+                    break;
+                }
+                (None, _) => comps.push(Component::ParentDir),
+                (Some(a), Some(b)) if comps.is_empty() && a == b => (),
+                (Some(a), Some(b)) if b == Component::CurDir => comps.push(a),
+                (Some(_), Some(b)) if b == Component::ParentDir => return None,
+                (Some(a), Some(_)) => {
+                    comps.push(Component::ParentDir);
+                    for _ in itb {
+                        comps.push(Component::ParentDir);
+                    }
+                    comps.push(a);
+                    comps.extend(ita.by_ref());
+                    break;
+                }
+            }
+        }
+        Some(comps.iter().map(|c| c.as_os_str()).collect())
+    }
+}